@@ -9,7 +9,7 @@ use indicatif::ProgressBar;
 use std::{
     fs::File,
     io::{self, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 #[derive(Debug)]
@@ -18,6 +18,7 @@ pub struct Archiver {
     output: PathBuf,
     target_kind: Option<ArchiveKind>,
     gitignore: bool,
+    prefix: Option<PathBuf>,
 
     #[cfg(feature = "indicatif")]
     progress_bar: Option<ProgressBar>,
@@ -30,6 +31,7 @@ impl Archiver {
             output,
             target_kind: None,
             gitignore: true,
+            prefix: None,
 
             #[cfg(feature = "indicatif")]
             progress_bar: None,
@@ -64,6 +66,22 @@ impl Archiver {
         }
     }
 
+    /// Prepends `prefix` to the path of every archive member so that the
+    /// archive extracts into a named directory
+    pub fn with_prefix(self, prefix: PathBuf) -> Self {
+        Self {
+            prefix: Some(prefix),
+            ..self
+        }
+    }
+
+    pub fn without_prefix(self) -> Self {
+        Self {
+            prefix: None,
+            ..self
+        }
+    }
+
     #[cfg(feature = "indicatif")]
     pub fn with_progress_bar(self, progress_bar: ProgressBar) -> Self {
         Self {
@@ -112,14 +130,20 @@ impl Archiver {
         Ok(Box::new(self.find_input_paths()?))
     }
 
+    fn arch_path(&self, input_path: &Path) -> PathBuf {
+        let arch_path = input_path.strip_prefix(&self.input).expect("not a prefix");
+        if let Some(prefix) = self.prefix.as_ref() {
+            prefix.join(arch_path)
+        } else {
+            arch_path.to_path_buf()
+        }
+    }
+
     fn create_tar<W: WriteFinish>(&self, writer: W) -> Result<()> {
         let mut tar = tar::Builder::new(writer);
 
         for input_path in self.input_paths()? {
-            let arch_path = input_path
-                .strip_prefix(&self.input)
-                .expect("not a prefix")
-                .to_path_buf();
+            let arch_path = self.arch_path(&input_path);
             tar.append_file(arch_path, &mut File::open(input_path)?)?;
         }
 
@@ -142,7 +166,8 @@ impl Archiver {
                     )),
 
                     Some(Compression::Zstandard) => {
-                        let n_workers = std::thread::available_parallelism()?.get() as u32;
+                        let n_workers =
+                            std::thread::available_parallelism().map_or(1, |n| n.get() as u32);
 
                         #[cfg(feature = "tracing")]
                         tracing::debug!("n_workers = {}", n_workers);
@@ -151,7 +176,15 @@ impl Archiver {
                             output_file,
                             zstd::DEFAULT_COMPRESSION_LEVEL,
                         )?;
-                        zst.multithread(n_workers)?;
+                        // zstd builds without thread support reject this, in
+                        // which case we compress on the current thread
+                        if let Err(_err) = zst.multithread(n_workers) {
+                            #[cfg(feature = "tracing")]
+                            tracing::warn!(
+                                "Multithreaded zstd compression is unavailable, falling back to a single thread: {}",
+                                _err
+                            );
+                        }
 
                         self.create_tar(ZstdWriteFinish(zst))
                     }
@@ -163,11 +196,8 @@ impl Archiver {
                 let mut zip = zip::write::ZipWriter::new(output_file);
                 let zip_opts = zip::write::SimpleFileOptions::default();
                 for input_path in self.input_paths()? {
-                    let arch_name = input_path
-                        .strip_prefix(&self.input)
-                        .expect("not a prefix")
-                        .to_string_lossy();
-                    zip.start_file(arch_name, zip_opts)?;
+                    let arch_path = self.arch_path(&input_path);
+                    zip.start_file(arch_path.to_string_lossy(), zip_opts)?;
                     io::copy(&mut File::open(input_path)?, &mut zip)?;
                 }
                 Ok(zip.finish()?.flush()?)
@@ -10,6 +10,9 @@ use std::{
     path::PathBuf,
 };
 
+#[cfg(feature = "indicatif")]
+const ZSTD_FRAME_HEADER_SIZE_MAX: usize = 18;
+
 #[derive(Debug)]
 pub struct Unarchiver {
     input: PathBuf,
@@ -74,43 +77,61 @@ impl Unarchiver {
         }
     }
 
+    #[cfg(not(feature = "indicatif"))]
+    fn create_reader(&self) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(File::open(&self.input)?))
+    }
+
+    /// Tracks the decompressed bytes read from the archive if its uncompressed
+    /// size is known. Falls back to tracking the compressed bytes read from
+    /// the input file otherwise.
     #[cfg(feature = "indicatif")]
-    fn create_readseeker(&self) -> io::Result<Box<dyn ReadSeek>> {
-        if let Some(pb) = &self.progress_bar {
-            Ok(Box::new(crate::indicatif::IndicatifReader::for_file(
-                File::open(&self.input)?,
+    fn create_tar_reader(&self, compression: Option<Compression>) -> io::Result<Box<dyn Read>> {
+        let Some(pb) = &self.progress_bar else {
+            return decoder(Box::new(File::open(&self.input)?), compression);
+        };
+        match tar_uncompressed_size(&self.input, compression)? {
+            Some(size) => Ok(Box::new(crate::indicatif::IndicatifReader::new(
+                decoder(Box::new(File::open(&self.input)?), compression)?,
                 pb.clone(),
-            )?))
-        } else {
-            Ok(Box::new(File::open(&self.input)?))
+                size,
+            ))),
+            None => decoder(self.create_reader()?, compression),
         }
     }
 
     #[cfg(not(feature = "indicatif"))]
-    fn create_reader(&self) -> io::Result<Box<dyn Read>> {
-        Ok(Box::new(File::open(&self.input)?))
+    fn create_tar_reader(&self, compression: Option<Compression>) -> io::Result<Box<dyn Read>> {
+        decoder(self.create_reader()?, compression)
+    }
+
+    #[cfg(feature = "indicatif")]
+    fn start_progress(&self, length: Option<u128>) {
+        if let Some(pb) = &self.progress_bar {
+            pb.set_position(0);
+            pb.set_length(length.map_or(0, |length| length as u64));
+        }
     }
 
     #[cfg(not(feature = "indicatif"))]
-    fn create_readseeker(&self) -> io::Result<Box<dyn ReadSeek>> {
-        Ok(Box::new(File::open(&self.input)?))
+    fn start_progress(&self, _length: Option<u128>) {}
+
+    #[cfg(feature = "indicatif")]
+    fn inc_progress(&self, delta: u64) {
+        if let Some(pb) = &self.progress_bar {
+            pb.inc(delta);
+        }
     }
 
+    #[cfg(not(feature = "indicatif"))]
+    fn inc_progress(&self, _delta: u64) {}
+
     pub fn synchronously(self) -> Result<()> {
         match self.source_kind.or_else(|| self.input.archive_kind()) {
             None => Err(Error::UnsupportedCompression),
 
             Some(ArchiveKind::Tar(compression)) => {
-                let input_file: Box<dyn Read> = match compression {
-                    None => self.create_reader()?,
-                    Some(Compression::Gzip) => {
-                        Box::new(flate2::read::MultiGzDecoder::new(self.create_reader()?))
-                    }
-                    Some(Compression::Zstandard) => {
-                        Box::new(zstd::stream::read::Decoder::new(self.create_reader()?)?)
-                    }
-                };
-                let mut tar = tar::Archive::new(input_file);
+                let mut tar = tar::Archive::new(self.create_tar_reader(compression)?);
 
                 for tar_entry in tar.entries()? {
                     let mut tar_entry = tar_entry?;
@@ -125,13 +146,14 @@ impl Unarchiver {
             }
 
             Some(ArchiveKind::Zip) => {
-                let mut zip = zip::read::ZipArchive::new(self.create_readseeker()?)?;
+                let mut zip = zip::read::ZipArchive::new(File::open(&self.input)?)?;
+                self.start_progress(zip.decompressed_size());
                 for i in 0..zip.len() {
                     let mut src_file = zip.by_index(i)?;
                     let dst_path = self.output.join(src_file.mangled_name());
                     fs::create_dir_all(dst_path.parent().expect("dest path had no parent"))?;
                     let mut dst_file = File::create(dst_path)?;
-                    io::copy(&mut src_file, &mut dst_file)?;
+                    self.inc_progress(io::copy(&mut src_file, &mut dst_file)?);
                 }
                 Ok(())
             }
@@ -144,5 +166,48 @@ impl Unarchiver {
     }
 }
 
+fn decoder(reader: Box<dyn Read>, compression: Option<Compression>) -> io::Result<Box<dyn Read>> {
+    Ok(match compression {
+        None => reader,
+        Some(Compression::Gzip) => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+        Some(Compression::Zstandard) => Box::new(zstd::stream::read::Decoder::new(reader)?),
+    })
+}
+
+/// The uncompressed size of a tar archive if it is recorded: the file size
+/// for plain tarballs, the ISIZE trailer for gzip and the frame content size
+/// for zstd (only written when the size was known at compression time)
+#[cfg(feature = "indicatif")]
+fn tar_uncompressed_size(
+    path: &std::path::Path,
+    compression: Option<Compression>,
+) -> io::Result<Option<u64>> {
+    let mut file = File::open(path)?;
+    let length = file.seek(io::SeekFrom::End(0))?;
+    match compression {
+        None => Ok(Some(length)),
+        Some(Compression::Gzip) => {
+            // ISIZE is the size modulo 2^32 so it is only trusted when the
+            // compressed archive itself is smaller than that
+            if length < 4 || length >= 1 << 32 {
+                return Ok(None);
+            }
+            let mut isize = [0; 4];
+            file.seek(io::SeekFrom::End(-4))?;
+            file.read_exact(&mut isize)?;
+            Ok(Some(u32::from_le_bytes(isize) as u64).filter(|size| *size >= length))
+        }
+        Some(Compression::Zstandard) => {
+            let mut header = Vec::with_capacity(ZSTD_FRAME_HEADER_SIZE_MAX);
+            file.seek(io::SeekFrom::Start(0))?;
+            file.take(ZSTD_FRAME_HEADER_SIZE_MAX as u64)
+                .read_to_end(&mut header)?;
+            Ok(zstd::zstd_safe::get_frame_content_size(&header)
+                .ok()
+                .flatten())
+        }
+    }
+}
+
 pub trait ReadSeek: Read + Seek {}
 impl<T: Read + Seek> ReadSeek for T {}
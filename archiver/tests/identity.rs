@@ -37,12 +37,19 @@ fn create_unarchiver(input: &Path, output: &Path) -> Unarchiver {
 }
 
 fn run_test_identity(src_dir: &Path, arch_kind: ArchiveKind) {
+    run_test_identity_with_prefix(src_dir, arch_kind, None)
+}
+
+fn run_test_identity_with_prefix(src_dir: &Path, arch_kind: ArchiveKind, prefix: Option<&Path>) {
     let src_entries = scan_data_dir(src_dir).unwrap();
 
     let arch_path = NamedTempFile::new().unwrap();
 
-    create_archiver(src_dir, arch_path.path())
-        .with_target_kind(arch_kind)
+    let mut archiver = create_archiver(src_dir, arch_path.path()).with_target_kind(arch_kind);
+    if let Some(prefix) = prefix {
+        archiver = archiver.with_prefix(prefix.to_path_buf());
+    }
+    archiver
         .synchronously()
         .expect("Cannot run archiver synchronously");
     // assert!(arch_path.path().with_extension("").metadata().is_err());
@@ -54,7 +61,7 @@ fn run_test_identity(src_dir: &Path, arch_kind: ArchiveKind) {
         .expect("Cannot run unarchiver synchronously");
     // assert!(arch_path.path().with_extension("").metadata().is_err());
 
-    let dst_entries = scan_data_dir(dst_dir.path()).unwrap();
+    let dst_entries = scan_data_dir(dst_dir.path().join(prefix.unwrap_or(Path::new("")))).unwrap();
     let dst_keys = dst_entries.keys().collect::<HashSet<_>>();
     let src_keys = src_entries.keys().collect::<HashSet<_>>();
     let diff_keys = src_keys.symmetric_difference(&dst_keys).collect::<Vec<_>>();
@@ -108,6 +115,22 @@ fn test_identity_zip() {
     run_test_identity(data_dir(), ArchiveKind::Zip);
 }
 
+#[test]
+fn test_identity_tar_zst_with_prefix() {
+    tracing_setup();
+    run_test_identity_with_prefix(
+        data_dir(),
+        ArchiveKind::Tar(Some(Compression::Zstandard)),
+        Some(Path::new("dataset-v3")),
+    );
+}
+
+#[test]
+fn test_identity_zip_with_prefix() {
+    tracing_setup();
+    run_test_identity_with_prefix(data_dir(), ArchiveKind::Zip, Some(Path::new("dataset-v3")));
+}
+
 #[tracing::instrument]
 fn generate_data_dir(
     num_entries: u32,
@@ -1,8 +1,14 @@
 use serde::{de, ser, Deserialize, Serialize};
 use std::{
-    borrow::Cow, collections::HashMap, convert::Infallible, fmt, path::PathBuf, str::FromStr,
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
 };
 use thiserror::Error;
+use toml_edit::{DocumentMut, Item, TableLike, Value};
 
 pub use pep440_rs::{self, Version};
 pub use pep508_rs::{self, PackageName, Requirement};
@@ -27,6 +33,18 @@ pub enum VersionError {
     VersionReleaseTooManyFields,
 }
 
+#[derive(Error, Debug)]
+pub enum ProjectValidationError {
+    #[error("No [tool.aqora] section found")]
+    MissingAqoraConfig,
+    #[error(transparent)]
+    Version(#[from] VersionError),
+    #[error(transparent)]
+    UseCase(#[from] UseCaseConfigValidationError),
+    #[error("Invalid test {0}: {1}")]
+    Test(String, #[source] TestConfigError),
+}
+
 impl PyProject {
     pub fn name(&self) -> Option<&str> {
         self.project.as_ref().map(|project| project.name.as_str())
@@ -40,6 +58,56 @@ impl PyProject {
         }
     }
 
+    pub fn dependencies(&self) -> &[Requirement] {
+        self.project
+            .as_ref()
+            .and_then(|project| project.dependencies.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Adds a dependency, replacing any existing requirement on the same package
+    pub fn add_dependency(&mut self, requirement: Requirement) {
+        let dependencies = self
+            .project
+            .get_or_insert_with(|| Project::new(String::new()))
+            .dependencies
+            .get_or_insert_with(Vec::new);
+        if let Some(index) = dependencies
+            .iter()
+            .position(|existing| existing.name == requirement.name)
+        {
+            // keep the first requirement's position and drop any duplicates
+            let name = requirement.name.clone();
+            dependencies[index] = requirement;
+            let mut seen = false;
+            dependencies.retain(|existing| {
+                if existing.name != name {
+                    true
+                } else {
+                    !std::mem::replace(&mut seen, true)
+                }
+            });
+        } else {
+            dependencies.push(requirement);
+        }
+    }
+
+    /// Removes every requirement on the package and returns them
+    pub fn remove_dependency(&mut self, name: &PackageName) -> Vec<Requirement> {
+        let Some(dependencies) = self
+            .project
+            .as_mut()
+            .and_then(|project| project.dependencies.as_mut())
+        else {
+            return Vec::new();
+        };
+        let (removed, kept) = std::mem::take(dependencies)
+            .into_iter()
+            .partition(|requirement| &requirement.name == name);
+        *dependencies = kept;
+        removed
+    }
+
     pub fn version(&self) -> Option<Version> {
         self.project
             .as_ref()
@@ -59,6 +127,31 @@ impl PyProject {
         }
     }
 
+    /// Runs every static check on the project and returns all the problems found
+    pub fn validate_all(&self) -> Vec<ProjectValidationError> {
+        let mut errors = Vec::new();
+        if let Err(err) = self.validate_version() {
+            errors.push(err.into());
+        }
+        match self.aqora() {
+            Some(AqoraConfig::UseCase(use_case)) => {
+                if let Err(err) = use_case.validate() {
+                    errors.push(err.into());
+                }
+                let mut test_names = use_case.tests.keys().collect::<Vec<_>>();
+                test_names.sort();
+                for name in test_names {
+                    if let Err(err) = use_case.for_test(name) {
+                        errors.push(ProjectValidationError::Test(name.clone(), err));
+                    }
+                }
+            }
+            Some(AqoraConfig::Submission(_)) => {}
+            None => errors.push(ProjectValidationError::MissingAqoraConfig),
+        }
+        errors
+    }
+
     pub fn aqora(&self) -> Option<&AqoraConfig> {
         self.tool.as_ref().and_then(|tool| tool.aqora.as_ref())
     }
@@ -74,6 +167,128 @@ impl PyProject {
     pub fn toml(&self) -> Result<String, toml::ser::Error> {
         toml::to_string(self)
     }
+
+    /// Writes the project into an existing document, only touching the values
+    /// that differ from what the document already describes so that comments,
+    /// formatting and key order are kept.
+    pub fn apply_to_document(&self, doc: &mut DocumentMut) -> Result<(), TomlDocumentError> {
+        let old = PyProject::from_toml(doc.to_string())?
+            .toml()?
+            .parse::<DocumentMut>()?;
+        let new = self.toml()?.parse::<DocumentMut>()?;
+        apply_table_diff(doc.as_table_mut(), old.as_table(), new.as_table());
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum TomlDocumentError {
+    #[error(transparent)]
+    Parse(#[from] toml_edit::TomlError),
+    #[error(transparent)]
+    Deserialize(#[from] toml::de::Error),
+    #[error(transparent)]
+    Serialize(#[from] toml::ser::Error),
+}
+
+fn values_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => a.value() == b.value(),
+        (Value::Integer(a), Value::Integer(b)) => a.value() == b.value(),
+        (Value::Float(a), Value::Float(b)) => a.value() == b.value(),
+        (Value::Boolean(a), Value::Boolean(b)) => a.value() == b.value(),
+        (Value::Datetime(a), Value::Datetime(b)) => a.value() == b.value(),
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| values_eq(a, b))
+        }
+        (Value::InlineTable(a), Value::InlineTable(b)) => tables_eq(a, b),
+        _ => false,
+    }
+}
+
+fn tables_eq(a: &dyn TableLike, b: &dyn TableLike) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .all(|(key, a)| b.get(key).map_or(false, |b| items_eq(a, b)))
+}
+
+fn items_eq(a: &Item, b: &Item) -> bool {
+    if let (Some(a), Some(b)) = (a.as_table_like(), b.as_table_like()) {
+        return tables_eq(a, b);
+    }
+    match (a, b) {
+        (Item::Value(a), Item::Value(b)) => values_eq(a, b),
+        (Item::ArrayOfTables(a), Item::ArrayOfTables(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| tables_eq(a, b))
+        }
+        _ => false,
+    }
+}
+
+/// Updates `doc` with the keys where `new` differs from `old`, where `old` is
+/// `doc` as it round-trips through [`PyProject`]. Comparing against `old`
+/// rather than `doc` itself ignores differences in representation, e.g. a
+/// function given as a string in `doc` but serialized as a table.
+fn apply_table_diff(doc: &mut dyn TableLike, old: &dyn TableLike, new: &dyn TableLike) {
+    let removed = old
+        .iter()
+        .map(|(key, _)| key.to_string())
+        .filter(|key| new.get(key).is_none())
+        .collect::<Vec<_>>();
+    for key in removed {
+        doc.remove(&key);
+    }
+    for (key, new) in new.iter() {
+        let Some(old) = old.get(key) else {
+            doc.insert(key, new.clone());
+            continue;
+        };
+        if items_eq(old, new) {
+            continue;
+        }
+        match doc.get_mut(key) {
+            Some(doc) => apply_item_diff(doc, old, new),
+            None => {
+                doc.insert(key, new.clone());
+            }
+        }
+    }
+}
+
+fn apply_item_diff(doc: &mut Item, old: &Item, new: &Item) {
+    if let (Some(doc), Some(old), Some(new)) = (
+        doc.as_table_like_mut(),
+        old.as_table_like(),
+        new.as_table_like(),
+    ) {
+        apply_table_diff(doc, old, new);
+        return;
+    }
+    if let (Item::ArrayOfTables(doc), Item::ArrayOfTables(old), Item::ArrayOfTables(new)) =
+        (&mut *doc, old, new)
+    {
+        if doc.len() == old.len() {
+            while doc.len() > new.len() {
+                doc.remove(doc.len() - 1);
+            }
+            for (index, new) in new.iter().enumerate() {
+                if let (Some(doc), Some(old)) = (doc.get_mut(index), old.get(index)) {
+                    apply_table_diff(doc, old, new);
+                    continue;
+                }
+                doc.push(new.clone());
+            }
+            return;
+        }
+    }
+    match (&mut *doc, new) {
+        (Item::Value(doc), Item::Value(new)) => {
+            let decor = doc.decor().clone();
+            *doc = new.clone();
+            *doc.decor_mut() = decor;
+        }
+        (doc, new) => *doc = new.clone(),
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -83,6 +298,7 @@ pub struct Tools {
     pub rest: Option<toml::Value>,
 }
 
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum AqoraConfig {
@@ -112,10 +328,70 @@ impl AqoraConfig {
             AqoraConfig::Submission(submission) => Some(submission),
         }
     }
+
+    pub fn tmp_dir(&self) -> Option<&Path> {
+        match self {
+            AqoraConfig::UseCase(use_case) => use_case.tmp_dir.as_deref(),
+            AqoraConfig::Submission(submission) => submission.tmp_dir.as_deref(),
+        }
+    }
+
+    pub fn generated_dir(&self) -> Option<&Path> {
+        match self {
+            AqoraConfig::UseCase(use_case) => use_case.generated_dir.as_deref(),
+            AqoraConfig::Submission(submission) => submission.generated_dir.as_deref(),
+        }
+    }
+
+    pub fn cli(&self) -> Option<&CliDefaults> {
+        match self {
+            AqoraConfig::UseCase(use_case) => use_case.cli.as_ref(),
+            AqoraConfig::Submission(submission) => submission.cli.as_ref(),
+        }
+    }
+
+    /// All the functions referenced by the config, including test overrides
+    pub fn function_defs(&self) -> Vec<&FunctionDef> {
+        match self {
+            AqoraConfig::UseCase(use_case) => {
+                let mut defs = Vec::new();
+                for layer in &use_case.layers {
+                    defs.extend(
+                        [
+                            &layer.transform,
+                            &layer.context,
+                            &layer.metric,
+                            &layer.branch,
+                        ]
+                        .into_iter()
+                        .flatten(),
+                    );
+                }
+                for test in use_case.tests.values() {
+                    for layer_override in test.overrides.values() {
+                        defs.extend(
+                            [
+                                &layer_override.transform,
+                                &layer_override.context,
+                                &layer_override.metric,
+                                &layer_override.branch,
+                            ]
+                            .into_iter()
+                            .flatten(),
+                        );
+                    }
+                    defs.extend(test.refs.values());
+                }
+                defs
+            }
+            AqoraConfig::Submission(submission) => submission.refs.values().collect(),
+        }
+    }
 }
 
 pub type RefMap = HashMap<String, FunctionDef>;
 
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AqoraUseCaseConfig {
     pub competition: Option<String>,
@@ -127,8 +403,30 @@ pub struct AqoraUseCaseConfig {
     pub layers: Vec<LayerConfig>,
     #[serde(default)]
     pub tests: HashMap<String, TestConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tmp_dir: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generated_dir: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cli: Option<CliDefaults>,
 }
 
+/// A TOML file referenced by `include` that contributes layers and tests to a
+/// use case. Fragments may include further fragments relative to themselves.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct UseCaseFragment {
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    layers: Vec<LayerConfig>,
+    #[serde(default)]
+    tests: HashMap<String, TestConfig>,
+}
+
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LayerConfig {
     pub name: String,
@@ -138,6 +436,7 @@ pub struct LayerConfig {
     pub branch: Option<FunctionDef>,
 }
 
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LayerOverride {
     pub transform: Option<FunctionDef>,
@@ -146,6 +445,7 @@ pub struct LayerOverride {
     pub branch: Option<FunctionDef>,
 }
 
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TestConfig {
     #[serde(default)]
@@ -155,6 +455,7 @@ pub struct TestConfig {
     pub aggregator: Option<PathStr<'static>>,
     #[serde(default)]
     pub overrides: HashMap<String, LayerOverride>,
+    #[cfg_attr(feature = "schema", schemars(with = "Option<serde_json::Value>"))]
     pub expected: Option<toml::Value>,
 }
 
@@ -164,16 +465,43 @@ pub enum TestConfigError {
     TestNotFound(String),
     #[error("Layer not found: {0}")]
     LayerNotFound(String),
+    #[error("Layer {0} is defined more than once")]
+    DuplicateLayer(String),
+    #[error("Environment variable not found: {0}")]
+    EnvVarNotFound(String),
     #[error(transparent)]
     PathStrReplaceError(#[from] PathStrReplaceError),
 }
 
+#[derive(Error, Debug)]
+pub enum IncludeError {
+    #[error("Invalid include pattern {0}: {1}")]
+    Pattern(String, glob::PatternError),
+    #[error("No files found matching include {0}")]
+    Missing(String),
+    #[error("Failed to read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("Failed to parse {0}: {1}")]
+    Parse(PathBuf, toml::de::Error),
+    #[error("Include cycle detected at {0}")]
+    Cycle(PathBuf),
+    #[error("{kind} {name} is defined in both {first} and {second}")]
+    Duplicate {
+        kind: &'static str,
+        name: String,
+        first: PathBuf,
+        second: PathBuf,
+    },
+}
+
 #[derive(Error, Debug)]
 pub enum UseCaseConfigValidationError {
     #[error("Generator contains a reference")]
     GeneratorContainsRef,
     #[error("Aggregator contains a reference")]
     AggregatorContainsRef,
+    #[error("Layer {0} is defined more than once")]
+    DuplicateLayer(String),
 }
 
 impl AqoraUseCaseConfig {
@@ -200,6 +528,24 @@ impl AqoraUseCaseConfig {
         Ok(())
     }
 
+    /// Expands `${VAR}` and `$VAR` in the `data` and `template` paths,
+    /// including the `data` of each test. `$$` is a literal `$`.
+    pub fn expand_env(&mut self) -> Result<(), TestConfigError> {
+        let paths = std::iter::once(&mut self.data)
+            .chain(self.template.as_mut())
+            .chain(
+                self.tests
+                    .values_mut()
+                    .filter_map(|test| test.data.as_mut()),
+            );
+        for path in paths {
+            if let Some(value) = path.to_str() {
+                *path = PathBuf::from(expand_env_vars(value, |name| std::env::var(name).ok())?);
+            }
+        }
+        Ok(())
+    }
+
     pub fn for_test(&self, test_name: &str) -> Result<AqoraUseCaseConfig, TestConfigError> {
         let mut out = self.clone();
         let test = self
@@ -216,11 +562,15 @@ impl AqoraUseCaseConfig {
             out.aggregator.clone_from(aggregator);
         }
         for (layer_name, layer_override) in test.overrides.iter() {
-            if let Some(layer) = out
+            let mut matching = out
                 .layers
                 .iter_mut()
-                .find(|layer| layer.name == *layer_name)
-            {
+                .filter(|layer| layer.name == *layer_name);
+            let layer = matching.next();
+            if matching.next().is_some() {
+                return Err(TestConfigError::DuplicateLayer(layer_name.to_string()));
+            }
+            if let Some(layer) = layer {
                 for (function, override_function) in [
                     (&mut layer.transform, &layer_override.transform),
                     (&mut layer.context, &layer_override.context),
@@ -239,6 +589,87 @@ impl AqoraUseCaseConfig {
         Ok(out)
     }
 
+    /// Drops every layer after `only_through` and bypasses the transform of
+    /// the layers named in `skip`, so their input is passed through unchanged.
+    /// Fails if any of the named layers does not exist.
+    pub fn select_layers(
+        &mut self,
+        skip: &[String],
+        only_through: Option<&str>,
+    ) -> Result<(), TestConfigError> {
+        let position = |name: &str| {
+            self.layers
+                .iter()
+                .position(|layer| layer.name == name)
+                .ok_or_else(|| TestConfigError::LayerNotFound(name.to_string()))
+        };
+        for name in skip {
+            position(name)?;
+        }
+        if let Some(name) = only_through {
+            let index = position(name)?;
+            self.layers.truncate(index + 1);
+        }
+        for layer in self.layers.iter_mut() {
+            if skip.contains(&layer.name) {
+                layer.transform = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Merges the fragments matched by `include` (relative to `base_dir`) into
+    /// the config and clears `include`. Layers and tests defined in the project
+    /// itself take precedence over included ones, fragment layers are appended
+    /// in include order, and the same name defined by two fragments is an error.
+    /// Includes are only resolved to run a project locally, an uploaded
+    /// pyproject.toml keeps `include` as written.
+    pub fn resolve_includes(&mut self, base_dir: impl AsRef<Path>) -> Result<(), IncludeError> {
+        let include = std::mem::take(&mut self.include);
+        let mut fragments = Vec::new();
+        collect_fragments(base_dir.as_ref(), &include, &mut Vec::new(), &mut fragments)?;
+
+        let own_layers = self
+            .layers
+            .iter()
+            .map(|layer| layer.name.clone())
+            .collect::<HashSet<_>>();
+        let own_tests = self.tests.keys().cloned().collect::<HashSet<_>>();
+        let mut included_layers = HashMap::<String, PathBuf>::new();
+        let mut included_tests = HashMap::<String, PathBuf>::new();
+        for (path, fragment) in fragments {
+            for layer in fragment.layers {
+                if own_layers.contains(&layer.name) {
+                    continue;
+                }
+                if let Some(first) = included_layers.insert(layer.name.clone(), path.clone()) {
+                    return Err(IncludeError::Duplicate {
+                        kind: "Layer",
+                        name: layer.name,
+                        first,
+                        second: path,
+                    });
+                }
+                self.layers.push(layer);
+            }
+            for (name, test) in fragment.tests {
+                if own_tests.contains(&name) {
+                    continue;
+                }
+                if let Some(first) = included_tests.insert(name.clone(), path.clone()) {
+                    return Err(IncludeError::Duplicate {
+                        kind: "Test",
+                        name,
+                        first,
+                        second: path,
+                    });
+                }
+                self.tests.insert(name, test);
+            }
+        }
+        Ok(())
+    }
+
     pub fn validate(&self) -> Result<(), UseCaseConfigValidationError> {
         if self.generator.has_ref() {
             return Err(UseCaseConfigValidationError::GeneratorContainsRef);
@@ -246,16 +677,164 @@ impl AqoraUseCaseConfig {
         if self.aggregator.has_ref() {
             return Err(UseCaseConfigValidationError::AggregatorContainsRef);
         }
+        let mut names = HashSet::new();
+        for layer in &self.layers {
+            if !names.insert(layer.name.as_str()) {
+                return Err(UseCaseConfigValidationError::DuplicateLayer(
+                    layer.name.clone(),
+                ));
+            }
+        }
         Ok(())
     }
 }
 
+fn expand_env_vars(
+    value: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<String, TestConfigError> {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(index) = rest.find('$') {
+        out.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+        let (name, after) = if let Some(braced) = rest.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => {
+                    out.push('$');
+                    continue;
+                }
+            }
+        } else if let Some(after) = rest.strip_prefix('$') {
+            out.push('$');
+            rest = after;
+            continue;
+        } else {
+            let end = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+        if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+            out.push('$');
+            continue;
+        }
+        out.push_str(
+            &lookup(name).ok_or_else(|| TestConfigError::EnvVarNotFound(name.to_string()))?,
+        );
+        rest = after;
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn collect_fragments(
+    base_dir: &Path,
+    patterns: &[String],
+    stack: &mut Vec<PathBuf>,
+    out: &mut Vec<(PathBuf, UseCaseFragment)>,
+) -> Result<(), IncludeError> {
+    for pattern in patterns {
+        let mut paths = glob::glob(&base_dir.join(pattern).to_string_lossy())
+            .map_err(|err| IncludeError::Pattern(pattern.clone(), err))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| IncludeError::Io(err.path().to_path_buf(), err.into_error()))?;
+        if paths.is_empty() {
+            return Err(IncludeError::Missing(pattern.clone()));
+        }
+        paths.sort();
+        for path in paths {
+            let canonical = path
+                .canonicalize()
+                .map_err(|err| IncludeError::Io(path.clone(), err))?;
+            if stack.contains(&canonical) {
+                return Err(IncludeError::Cycle(path));
+            }
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|err| IncludeError::Io(path.clone(), err))?;
+            let mut fragment: UseCaseFragment =
+                toml::from_str(&contents).map_err(|err| IncludeError::Parse(path.clone(), err))?;
+            let nested = std::mem::take(&mut fragment.include);
+            let fragment_dir = canonical.parent().unwrap_or(base_dir).to_path_buf();
+            out.push((path, fragment));
+            stack.push(canonical);
+            collect_fragments(&fragment_dir, &nested, stack, out)?;
+            stack.pop();
+        }
+    }
+    Ok(())
+}
+
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AqoraSubmissionConfig {
     pub competition: Option<String>,
     pub entity: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub targets: HashMap<String, SubmissionTarget>,
     #[serde(default)]
     pub refs: RefMap,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tmp_dir: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generated_dir: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cli: Option<CliDefaults>,
+}
+
+/// A named competition to submit to, set under `[tool.aqora.targets.<name>]`.
+/// Missing fields fall back to the top-level `competition` and `entity`.
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct SubmissionTarget {
+    pub competition: Option<String>,
+    pub entity: Option<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum SubmissionTargetError {
+    #[error("Target not found: {0}")]
+    NotFound(String),
+}
+
+impl AqoraSubmissionConfig {
+    /// Resolves the competition and entity to submit to. Without a `name` the
+    /// top-level `competition` and `entity` are used.
+    pub fn target(&self, name: Option<&str>) -> Result<SubmissionTarget, SubmissionTargetError> {
+        let Some(name) = name else {
+            return Ok(SubmissionTarget {
+                competition: self.competition.clone(),
+                entity: self.entity.clone(),
+            });
+        };
+        let target = self
+            .targets
+            .get(name)
+            .ok_or_else(|| SubmissionTargetError::NotFound(name.to_string()))?;
+        Ok(SubmissionTarget {
+            competition: target
+                .competition
+                .clone()
+                .or_else(|| self.competition.clone()),
+            entity: target.entity.clone().or_else(|| self.entity.clone()),
+        })
+    }
+}
+
+/// Project defaults for global command line options, set under
+/// `[tool.aqora.cli]`. Options given on the command line or through the
+/// environment take precedence.
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct CliDefaults {
+    pub max_concurrency: Option<usize>,
+    pub color: Option<String>,
+    pub dep_link_mode: Option<String>,
+    pub python: Option<String>,
+    pub uv: Option<PathBuf>,
+    pub no_prompt: Option<bool>,
 }
 
 #[derive(Clone, Serialize, Debug)]
@@ -330,6 +909,8 @@ pub struct PathStr<'a>(Cow<'a, [String]>);
 pub enum PathStrReplaceError {
     #[error("Ref not found: {0}")]
     RefNotFound(String),
+    #[error("Circular ref: ${}", .0.join(" -> $"))]
+    CircularRef(Vec<String>),
 }
 
 impl<'a> PathStr<'a> {
@@ -339,23 +920,40 @@ impl<'a> PathStr<'a> {
     pub fn name(&self) -> &str {
         self.0.last().unwrap()
     }
+    /// Replaces every `$ref` part with its value in `refs`. Values may
+    /// themselves contain refs, which are resolved in turn.
     pub fn replace_refs(
         &self,
         refs: &HashMap<String, PathStr>,
     ) -> Result<PathStr<'static>, PathStrReplaceError> {
         let mut out = Vec::new();
+        self.expand_refs(refs, &mut Vec::new(), &mut out)?;
+        Ok(PathStr(Cow::Owned(out)))
+    }
+    fn expand_refs(
+        &self,
+        refs: &HashMap<String, PathStr>,
+        stack: &mut Vec<String>,
+        out: &mut Vec<String>,
+    ) -> Result<(), PathStrReplaceError> {
         for part in self.0.iter() {
-            if let Some(ref_key) = part.strip_prefix('$') {
-                if let Some(replacement) = refs.get(ref_key) {
-                    out.extend(replacement.0.iter().cloned());
-                } else {
-                    return Err(PathStrReplaceError::RefNotFound(ref_key.to_string()));
-                }
-            } else {
+            let Some(ref_key) = part.strip_prefix('$') else {
                 out.push(part.clone());
+                continue;
+            };
+            if let Some(start) = stack.iter().position(|key| key == ref_key) {
+                let mut cycle = stack[start..].to_vec();
+                cycle.push(ref_key.to_string());
+                return Err(PathStrReplaceError::CircularRef(cycle));
             }
+            let replacement = refs
+                .get(ref_key)
+                .ok_or_else(|| PathStrReplaceError::RefNotFound(ref_key.to_string()))?;
+            stack.push(ref_key.to_string());
+            replacement.expand_refs(refs, stack, out)?;
+            stack.pop();
         }
-        Ok(PathStr(Cow::Owned(out)))
+        Ok(())
     }
     pub fn has_ref(&self) -> bool {
         self.0.iter().any(|part| part.starts_with('$'))
@@ -380,6 +978,57 @@ impl fmt::Debug for PathStr<'_> {
     }
 }
 
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for PathStr<'_> {
+    fn schema_name() -> String {
+        "PathStr".to_string()
+    }
+
+    fn json_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        serde_json::from_value(serde_json::json!({
+            "type": "string",
+            "description": "A dotted Python path such as `module.function`. Parts starting with `$` are refs",
+        }))
+        .expect("valid schema")
+    }
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for FunctionDef {
+    fn schema_name() -> String {
+        "FunctionDef".to_string()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        let path = gen.subschema_for::<PathStr>();
+        serde_json::from_value(serde_json::json!({
+            "oneOf": [
+                path,
+                {
+                    "type": "object",
+                    "properties": {
+                        "path": path,
+                        "notebook": {
+                            "type": "boolean",
+                            "description": "Whether the path points to a notebook",
+                            "default": false,
+                        },
+                    },
+                    "required": ["path"],
+                    "additionalProperties": false,
+                },
+            ],
+        }))
+        .expect("valid schema")
+    }
+}
+
+/// The JSON Schema of the `[tool.aqora]` section of a pyproject.toml
+#[cfg(feature = "schema")]
+pub fn json_schema() -> serde_json::Value {
+    serde_json::to_value(schemars::schema_for!(AqoraConfig)).expect("valid schema")
+}
+
 impl ser::Serialize for PathStr<'_> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -453,4 +1102,427 @@ mod tests {
         assert_eq!(replaced.to_string(), "foo.qux.quux.baz");
         assert!(!replaced.has_ref());
     }
+
+    #[test]
+    fn test_path_str_nested_refs() {
+        let refs = |pairs: &[(&str, &str)]| -> HashMap<String, PathStr> {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.parse().unwrap()))
+                .collect()
+        };
+        let path_str: PathStr = "$a".parse().unwrap();
+
+        let replaced = path_str
+            .replace_refs(&refs(&[("a", "$b"), ("b", "foo.bar")]))
+            .unwrap();
+        assert_eq!(replaced.to_string(), "foo.bar");
+
+        let err = path_str
+            .replace_refs(&refs(&[("a", "$b"), ("b", "$a")]))
+            .unwrap_err();
+        assert!(matches!(
+            &err,
+            PathStrReplaceError::CircularRef(cycle) if cycle == &["a", "b", "a"]
+        ));
+        assert_eq!(err.to_string(), "Circular ref: $a -> $b -> $a");
+
+        assert!(matches!(
+            path_str.replace_refs(&refs(&[("a", "x.$a")])),
+            Err(PathStrReplaceError::CircularRef(cycle)) if cycle == ["a", "a"]
+        ));
+    }
+
+    #[test]
+    fn test_validate_all() {
+        let project = PyProject::from_toml(
+            r#"
+[project]
+name = "use-case"
+
+[tool.aqora]
+type = "use_case"
+data = "data"
+generator = "use_case.generator"
+aggregator = "$aggregator"
+
+[[tool.aqora.layers]]
+name = "model"
+transform = "$model"
+
+[tool.aqora.tests.missing_layer.overrides.other]
+transform = "use_case.transform"
+
+[tool.aqora.tests.missing_ref]
+"#,
+        )
+        .unwrap();
+        let errors = project
+            .validate_all()
+            .into_iter()
+            .map(|err| err.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                "Project version is missing",
+                "Aggregator contains a reference",
+                "Invalid test missing_layer: Layer not found: other",
+                "Invalid test missing_ref: Ref not found: aggregator",
+            ]
+        );
+    }
+
+    fn include_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aqora-config-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (path, contents) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    fn include_use_case(include: &str) -> AqoraUseCaseConfig {
+        let project = PyProject::from_toml(format!(
+            r#"
+[tool.aqora]
+type = "use_case"
+data = "data"
+generator = "use_case.generator"
+aggregator = "use_case.aggregator"
+include = [{include:?}]
+
+[[tool.aqora.layers]]
+name = "model"
+transform = "use_case.model"
+
+[tool.aqora.tests.smoke]
+data = "project"
+"#
+        ))
+        .unwrap();
+        project.aqora().unwrap().as_use_case().unwrap().clone()
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn test_json_schema() {
+        let schema = json_schema();
+        let definitions = &schema["definitions"];
+        assert_eq!(definitions["PathStr"]["type"], "string");
+        let function_def = definitions["FunctionDef"]["oneOf"].as_array().unwrap();
+        assert_eq!(function_def.len(), 2);
+        assert_eq!(function_def[1]["required"], serde_json::json!(["path"]));
+        let variants = schema["oneOf"].to_string();
+        assert!(variants.contains("\"use_case\""));
+        assert!(variants.contains("\"submission\""));
+    }
+
+    #[test]
+    fn test_expand_env_vars() {
+        let lookup = |name: &str| match name {
+            "DATA_ROOT" => Some("/mnt/data".to_string()),
+            "SPLIT" => Some("test".to_string()),
+            _ => None,
+        };
+        let expand = |value: &str| expand_env_vars(value, lookup);
+        assert_eq!(expand("data").unwrap(), "data");
+        assert_eq!(expand("${DATA_ROOT}/split").unwrap(), "/mnt/data/split");
+        assert_eq!(expand("$DATA_ROOT/$SPLIT").unwrap(), "/mnt/data/test");
+        assert_eq!(expand("${DATA_ROOT}${SPLIT}").unwrap(), "/mnt/datatest");
+        assert_eq!(expand("price$$/$$$SPLIT").unwrap(), "price$/$test");
+        assert_eq!(expand("a/$/b").unwrap(), "a/$/b");
+        assert_eq!(expand("${unterminated").unwrap(), "${unterminated");
+        assert!(matches!(
+            expand("${MISSING}/data"),
+            Err(TestConfigError::EnvVarNotFound(name)) if name == "MISSING"
+        ));
+    }
+
+    #[test]
+    fn test_submission_targets() {
+        let project = PyProject::from_toml(
+            r#"
+[tool.aqora]
+type = "submission"
+competition = "default-competition"
+entity = "team"
+
+[tool.aqora.targets.other]
+competition = "other-competition"
+
+[tool.aqora.targets.solo]
+competition = "solo-competition"
+entity = "me"
+"#,
+        )
+        .unwrap();
+        let submission = project.aqora().unwrap().as_submission().unwrap();
+        let target = |competition: &str, entity: &str| SubmissionTarget {
+            competition: Some(competition.to_string()),
+            entity: Some(entity.to_string()),
+        };
+        assert_eq!(
+            submission.target(None).unwrap(),
+            target("default-competition", "team")
+        );
+        assert_eq!(
+            submission.target(Some("other")).unwrap(),
+            target("other-competition", "team")
+        );
+        assert_eq!(
+            submission.target(Some("solo")).unwrap(),
+            target("solo-competition", "me")
+        );
+        assert!(matches!(
+            submission.target(Some("missing")),
+            Err(SubmissionTargetError::NotFound(name)) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn test_dependencies() {
+        let mut project = PyProject::from_toml(
+            r#"
+[project]
+name = "submission"
+dependencies = ["numpy>=1.0", "pandas", "numpy[extra]"]
+"#,
+        )
+        .unwrap();
+        let names = |project: &PyProject| {
+            project
+                .dependencies()
+                .iter()
+                .map(|requirement| requirement.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&project), ["numpy>=1.0", "pandas", "numpy[extra]"]);
+
+        project.add_dependency("numpy~=2.0".parse().unwrap());
+        assert_eq!(names(&project), ["numpy~=2.0", "pandas"]);
+
+        project.add_dependency("scipy".parse().unwrap());
+        assert_eq!(names(&project), ["numpy~=2.0", "pandas", "scipy"]);
+
+        let removed = project.remove_dependency(&PackageName::new("pandas".to_string()).unwrap());
+        assert_eq!(removed.len(), 1);
+        assert_eq!(names(&project), ["numpy~=2.0", "scipy"]);
+
+        let mut empty = PyProject::from_toml("").unwrap();
+        assert!(empty.dependencies().is_empty());
+        empty.add_dependency("numpy".parse().unwrap());
+        assert_eq!(names(&empty), ["numpy"]);
+    }
+
+    #[test]
+    fn test_duplicate_layers() {
+        let project = PyProject::from_toml(
+            r#"
+[project]
+name = "use-case"
+version = "0.1.0"
+
+[tool.aqora]
+type = "use_case"
+data = "data"
+generator = "use_case.generator"
+aggregator = "use_case.aggregator"
+
+[[tool.aqora.layers]]
+name = "model"
+transform = "use_case.model"
+
+[[tool.aqora.layers]]
+name = "model"
+transform = "use_case.other_model"
+
+[tool.aqora.tests.override.overrides.model]
+transform = "use_case.transform"
+"#,
+        )
+        .unwrap();
+        let errors = project
+            .validate_all()
+            .into_iter()
+            .map(|err| err.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                "Layer model is defined more than once",
+                "Invalid test override: Layer model is defined more than once",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply_to_document() {
+        let original = r#"# Project metadata
+[project]
+name = "my-submission"  # the package name
+version = "0.1.0"
+dependencies = [
+    "numpy",  # for arrays
+]
+
+[tool.aqora]
+type = "submission"
+competition = "h2"
+
+# The solution
+[tool.aqora.refs]
+solution = "submission.solution"  # main entrypoint
+
+[tool.other]
+keep = true
+"#;
+        let mut project = PyProject::from_toml(original).unwrap();
+        project.set_name("renamed");
+        let mut doc = original.parse::<DocumentMut>().unwrap();
+        project.apply_to_document(&mut doc).unwrap();
+        let updated = doc.to_string();
+        assert_eq!(
+            updated,
+            original.replace(
+                r#"name = "my-submission"  # the package name"#,
+                r#"name = "renamed"  # the package name"#
+            )
+        );
+        assert_eq!(
+            PyProject::from_toml(&updated).unwrap().name().unwrap(),
+            "renamed"
+        );
+    }
+
+    #[test]
+    fn test_select_layers() {
+        let project = PyProject::from_toml(
+            r#"
+[tool.aqora]
+type = "use_case"
+data = "data"
+generator = "use_case.generator"
+aggregator = "use_case.aggregator"
+
+[[tool.aqora.layers]]
+name = "preprocess"
+transform = "use_case.preprocess"
+
+[[tool.aqora.layers]]
+name = "model"
+transform = "use_case.model"
+metric = "use_case.metric"
+
+[[tool.aqora.layers]]
+name = "postprocess"
+transform = "use_case.postprocess"
+"#,
+        )
+        .unwrap();
+        let use_case = project.aqora().unwrap().as_use_case().unwrap().clone();
+
+        let mut selected = use_case.clone();
+        selected
+            .select_layers(&["preprocess".to_string()], Some("model"))
+            .unwrap();
+        let names = selected
+            .layers
+            .iter()
+            .map(|layer| layer.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["preprocess", "model"]);
+        assert!(selected.layers[0].transform.is_none());
+        assert!(selected.layers[1].transform.is_some());
+        assert!(selected.layers[1].metric.is_some());
+
+        assert!(matches!(
+            use_case.clone().select_layers(&["missing".to_string()], None),
+            Err(TestConfigError::LayerNotFound(name)) if name == "missing"
+        ));
+        assert!(matches!(
+            use_case.clone().select_layers(&[], Some("missing")),
+            Err(TestConfigError::LayerNotFound(name)) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn test_resolve_includes() {
+        let dir = include_dir(
+            "includes",
+            &[
+                (
+                    "layers/a.toml",
+                    r#"
+include = ["../tests/*.toml"]
+
+[[layers]]
+name = "model"
+transform = "use_case.other_model"
+
+[[layers]]
+name = "metric"
+metric = "use_case.metric"
+"#,
+                ),
+                (
+                    "tests/smoke.toml",
+                    r#"
+[tests.smoke]
+data = "fragment"
+
+[tests.full]
+"#,
+                ),
+            ],
+        );
+        let mut use_case = include_use_case("layers/*.toml");
+        use_case.resolve_includes(&dir).unwrap();
+        assert!(use_case.include.is_empty());
+        assert_eq!(
+            use_case
+                .layers
+                .iter()
+                .map(|layer| layer.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["model", "metric"]
+        );
+        assert_eq!(
+            use_case.layers[0]
+                .transform
+                .as_ref()
+                .unwrap()
+                .path
+                .to_string(),
+            "use_case.model"
+        );
+        assert_eq!(
+            use_case.tests["smoke"].data.as_deref(),
+            Some(Path::new("project"))
+        );
+        assert!(use_case.tests.contains_key("full"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_includes_errors() {
+        let dir = include_dir(
+            "include-errors",
+            &[
+                ("a.toml", "include = [\"b.toml\"]\n"),
+                ("b.toml", "include = [\"a.toml\"]\n"),
+            ],
+        );
+        assert!(matches!(
+            include_use_case("a.toml").resolve_includes(&dir),
+            Err(IncludeError::Cycle(_))
+        ));
+        assert!(matches!(
+            include_use_case("missing/*.toml").resolve_includes(&dir),
+            Err(IncludeError::Missing(_))
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -3,6 +3,8 @@ use crate::python::{
     AsyncIterator, PyEnv,
 };
 use aqora_config::{AqoraUseCaseConfig, FunctionDef};
+#[cfg(feature = "clap")]
+use clap::{builder::PossibleValue, ValueEnum};
 use futures::prelude::*;
 use pyo3::{
     exceptions::PyValueError,
@@ -361,6 +363,64 @@ impl Evaluator {
     }
 }
 
+/// Built-in aggregation applied to the numeric metrics of the last layer
+/// with a metric, used in place of the configured aggregator
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregate {
+    Mean,
+    Median,
+    Sum,
+    P95,
+}
+
+impl Aggregate {
+    /// Aggregates `values`, or `None` if there are none. Quantiles interpolate
+    /// linearly between the closest values. Any NaN value makes the result NaN.
+    pub fn apply(&self, mut values: Vec<f64>) -> Option<f64> {
+        if values.is_empty() {
+            return None;
+        }
+        if values.iter().any(|value| value.is_nan()) {
+            return Some(f64::NAN);
+        }
+        Some(match self {
+            Self::Mean => values.iter().sum::<f64>() / values.len() as f64,
+            Self::Sum => values.iter().sum(),
+            Self::Median => {
+                values.sort_by(f64::total_cmp);
+                quantile(&values, 0.5)
+            }
+            Self::P95 => {
+                values.sort_by(f64::total_cmp);
+                quantile(&values, 0.95)
+            }
+        })
+    }
+}
+
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let pos = q * (sorted.len() - 1) as f64;
+    let (lower, upper) = (pos.floor() as usize, pos.ceil() as usize);
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (pos - lower as f64)
+}
+
+#[cfg(feature = "clap")]
+impl ValueEnum for Aggregate {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Mean, Self::Median, Self::Sum, Self::P95]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Mean => PossibleValue::new("mean"),
+            Self::Median => PossibleValue::new("median"),
+            Self::Sum => PossibleValue::new("sum"),
+            Self::P95 => PossibleValue::new("p95"),
+        })
+    }
+}
+
 pub struct Pipeline {
     generator: PyObject,
     aggregator: PyObject,
@@ -456,4 +516,94 @@ impl Pipeline {
         let mut out_stream = futures::stream::select(result, errs);
         out_stream.next().await.transpose()
     }
+
+    pub async fn aggregate_with(
+        &self,
+        results: impl Stream<Item = Result<EvaluationResult, (EvaluationResult, EvaluationError)>>,
+        aggregate: Aggregate,
+    ) -> Result<Option<PyObject>, EvaluationError> {
+        let mut results = std::pin::pin!(results);
+        let mut values = Vec::new();
+        while let Some(result) = results.next().await {
+            let result = result.map_err(|(_, err)| err)?;
+            values.extend(self.numeric_metrics(&result)?);
+        }
+        Ok(aggregate
+            .apply(values)
+            .map(|value| Python::with_gil(|py| value.into_py(py))))
+    }
+
+    fn numeric_metrics(&self, result: &EvaluationResult) -> Result<Vec<f64>, EvaluationError> {
+        let Some((name, evaluations)) = self.layers.iter().rev().find_map(|layer| {
+            result
+                .get(&layer.name)
+                .filter(|evaluations| evaluations.iter().any(|eval| eval.metric.is_some()))
+                .map(|evaluations| (&layer.name, evaluations))
+        }) else {
+            return Ok(Vec::new());
+        };
+        Python::with_gil(|py| {
+            evaluations
+                .iter()
+                .filter_map(|eval| eval.metric.as_ref())
+                .map(|metric| {
+                    metric.extract::<f64>(py).map_err(|_| {
+                        EvaluationError::custom(format!(
+                            "Metric for layer {name} is not numeric: {}",
+                            metric.as_ref(py)
+                        ))
+                    })
+                })
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantile() {
+        assert_eq!(quantile(&[1.0, 2.0, 3.0, 4.0], 0.5), 2.5);
+        assert_eq!(quantile(&[1.0, 2.0, 3.0], 0.5), 2.0);
+        assert_eq!(quantile(&[7.0], 0.95), 7.0);
+        let values = (0..=20).map(f64::from).collect::<Vec<_>>();
+        assert!((quantile(&values, 0.95) - 19.0).abs() < 1e-9);
+        assert!((quantile(&[0.0, 10.0], 0.95) - 9.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_aggregate_apply() {
+        let values = vec![4.0, 1.0, 3.0, 2.0];
+        assert_eq!(Aggregate::Mean.apply(values.clone()), Some(2.5));
+        assert_eq!(Aggregate::Sum.apply(values.clone()), Some(10.0));
+        assert_eq!(Aggregate::Median.apply(values.clone()), Some(2.5));
+        let p95 = Aggregate::P95.apply(values).unwrap();
+        assert!((p95 - 3.85).abs() < 1e-9, "{p95}");
+    }
+
+    #[test]
+    fn test_aggregate_apply_empty() {
+        for aggregate in [
+            Aggregate::Mean,
+            Aggregate::Median,
+            Aggregate::Sum,
+            Aggregate::P95,
+        ] {
+            assert_eq!(aggregate.apply(vec![]), None);
+        }
+    }
+
+    #[test]
+    fn test_aggregate_apply_nan() {
+        for aggregate in [
+            Aggregate::Mean,
+            Aggregate::Median,
+            Aggregate::Sum,
+            Aggregate::P95,
+        ] {
+            assert!(aggregate.apply(vec![1.0, f64::NAN, 3.0]).unwrap().is_nan());
+        }
+    }
 }
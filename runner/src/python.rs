@@ -255,14 +255,41 @@ impl PyEnv {
         })
     }
 
+    /// Creates the venv if needed and installs the runner into it. If the venv
+    /// directory did not exist before this call it is removed again when any
+    /// step fails, so that the next run does not pick up a half-created
+    /// environment.
     async fn ensure_venv(
         uv_path: impl AsRef<Path>,
         venv_path: impl AsRef<Path>,
         options: PyEnvOptions,
+    ) -> Result<(), EnvError> {
+        let path = venv_path.as_ref();
+        let create = !path.join("pyvenv.cfg").exists();
+        let created_dir = !path.exists();
+        match Self::install_venv(uv_path, path, create, options).await {
+            Err(err) if created_dir && path.exists() => {
+                if let Err(remove_err) = tokio::fs::remove_dir_all(path).await {
+                    tracing::warn!(
+                        "Failed to remove partially created venv {}: {remove_err}",
+                        path.display()
+                    );
+                }
+                Err(err)
+            }
+            res => res,
+        }
+    }
+
+    async fn install_venv(
+        uv_path: impl AsRef<Path>,
+        venv_path: impl AsRef<Path>,
+        create: bool,
+        options: PyEnvOptions,
     ) -> Result<(), EnvError> {
         let uv_path = uv_path.as_ref();
         let path = venv_path.as_ref();
-        if !path.join("pyvenv.cfg").exists() {
+        if create {
             let mut cmd = Command::new(uv_path);
             cmd.arg("venv")
                 .arg("--python")
@@ -1,46 +1,135 @@
 use crate::{
     commands::GlobalArgs,
-    dirs::{project_config_dir, project_venv_dir, read_pyproject},
+    dirs::{
+        locate_uv, project_config_dir, project_last_run_dir, project_venv_dir,
+        read_resolved_pyproject,
+    },
     error::{self, Result},
 };
 use clap::Args;
+use indicatif::HumanBytes;
 use serde::Serialize;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-#[derive(Args, Debug, Serialize)]
+#[derive(Args, Debug, Default, Serialize)]
 #[command(author, version, about)]
-pub struct Clean;
+pub struct Clean {
+    #[arg(long, help = "Remove the project virtual environment")]
+    pub venv: bool,
+    #[arg(long, help = "Clear the uv package cache")]
+    pub cache: bool,
+    #[arg(long, help = "Remove the results of the last test run")]
+    pub last_run: bool,
+    #[arg(long, help = "Remove the Python code generated from notebooks")]
+    pub notebooks: bool,
+    #[arg(
+        long,
+        help = "Remove ignored build artifacts such as __pycache__ and *.egg-info"
+    )]
+    pub generated: bool,
+    #[arg(
+        long,
+        help = "Remove everything, including the .aqora directory and the uv cache"
+    )]
+    pub all: bool,
+}
 
-async fn clean_dir(path: impl AsRef<Path>) -> Result<()> {
-    let path = path.as_ref();
-    let project_config_dir = project_config_dir(path);
-    if project_config_dir.exists() {
-        if let Err(err) = tokio::fs::remove_dir_all(&project_config_dir).await {
-            tracing::warn!(
-                "Failed to remove project config directory at {}: {}",
-                project_config_dir.display(),
-                err
-            );
+#[derive(Debug, Clone, Copy, Default)]
+struct CleanTargets {
+    config: bool,
+    venv: bool,
+    cache: bool,
+    last_run: bool,
+    notebooks: bool,
+    generated: bool,
+}
+
+impl CleanTargets {
+    fn describe(&self) -> Vec<&'static str> {
+        [
+            (self.config, "the .aqora directory"),
+            (self.venv, "the virtual environment"),
+            (self.cache, "the uv cache"),
+            (self.last_run && !self.config, "the last run results"),
+            (self.notebooks, "generated notebook code"),
+            (self.generated, "build artifacts"),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, name)| enabled.then_some(name))
+        .collect()
+    }
+}
+
+impl Clean {
+    /// Returns the selected targets, or `None` if no flag was given
+    fn targets(&self) -> Option<CleanTargets> {
+        if self.all {
+            Some(CleanTargets {
+                config: true,
+                venv: true,
+                cache: true,
+                last_run: true,
+                notebooks: true,
+                generated: true,
+            })
+        } else if self.venv || self.cache || self.last_run || self.notebooks || self.generated {
+            Some(CleanTargets {
+                config: false,
+                venv: self.venv,
+                cache: self.cache,
+                last_run: self.last_run,
+                notebooks: self.notebooks,
+                generated: self.generated,
+            })
+        } else {
+            None
         }
     }
-    let venv_dir = project_venv_dir(path);
-    if venv_dir.exists() {
-        if venv_dir.is_symlink() {
-            if let Err(err) = tokio::fs::remove_file(&venv_dir).await {
-                tracing::warn!(
-                    "Failed to remove project venv symlink at {}: {}",
-                    venv_dir.display(),
-                    err
-                );
-            }
-        } else if let Err(err) = tokio::fs::remove_dir_all(&venv_dir).await {
-            tracing::warn!(
-                "Failed to remove project venv directory at {}: {}",
-                venv_dir.display(),
-                err
-            );
+}
+
+fn path_size(path: &Path) -> u64 {
+    ignore::WalkBuilder::new(path)
+        .standard_filters(false)
+        .build()
+        .flatten()
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+async fn remove_path(path: &Path, what: &str) -> u64 {
+    if !path.exists() && !path.is_symlink() {
+        return 0;
+    }
+    let (size, res) = if path.is_symlink() || path.is_file() {
+        (0, tokio::fs::remove_file(path).await)
+    } else {
+        (path_size(path), tokio::fs::remove_dir_all(path).await)
+    };
+    match res {
+        Ok(()) => size,
+        Err(err) => {
+            tracing::warn!("Failed to remove {what} at {}: {}", path.display(), err);
+            0
         }
     }
+}
+
+async fn clean_dir(path: impl AsRef<Path>, targets: CleanTargets) -> Result<u64> {
+    let path = path.as_ref();
+    let mut freed = 0;
+    if targets.config {
+        freed += remove_path(&project_config_dir(path), "project config directory").await;
+    } else if targets.last_run {
+        freed += remove_path(&project_last_run_dir(path), "last run directory").await;
+    }
+    if targets.venv {
+        freed += remove_path(&project_venv_dir(path), "project venv").await;
+    }
+    if !targets.notebooks && !targets.generated {
+        return Ok(freed);
+    }
     let gitignore = {
         let mut builder = ignore::gitignore::GitignoreBuilder::new(path);
         if let Some(err) = builder.add(path.join(".gitignore")) {
@@ -56,50 +145,130 @@ async fn clean_dir(path: impl AsRef<Path>) -> Result<()> {
             .flatten()
             .map(|entry| entry.into_path())
         {
-            if !gitignore
-                .matched_path_or_any_parents(&entry, entry.is_dir())
-                .is_ignore()
+            if !entry.exists()
+                || !gitignore
+                    .matched_path_or_any_parents(&entry, entry.is_dir())
+                    .is_ignore()
             {
                 continue;
             }
-            if entry.is_dir()
+            let name = entry.file_name().and_then(|name| name.to_str());
+            if entry.is_dir() && name == Some("__aqora__") {
+                if targets.notebooks {
+                    freed += remove_path(&entry, "directory").await;
+                }
+            } else if entry.is_dir()
                 && (entry.extension().map_or(false, |ext| ext == "egg-info")
-                    || entry
-                        .file_name()
-                        .map_or(false, |name| name == "__pycache__" || name == "__aqora__"))
+                    || name == Some("__pycache__"))
             {
-                if let Err(err) = tokio::fs::remove_dir_all(&entry).await {
-                    tracing::warn!("Failed to remove directory at {}: {}", entry.display(), err);
+                if targets.generated {
+                    freed += remove_path(&entry, "directory").await;
                 }
             } else if entry.is_file()
                 && entry
                     .extension()
                     .and_then(|ext| ext.to_str())
                     .map_or(false, |ext| matches!(ext, "pyc" | "pyo" | "pyd" | "egg"))
+                && targets.generated
             {
-                if let Err(err) = tokio::fs::remove_file(&entry).await {
-                    tracing::warn!("Failed to remove file at {}: {}", entry.display(), err);
-                }
+                freed += remove_path(&entry, "file").await;
             }
         }
     }
-    Ok(())
+    Ok(freed)
+}
+
+async fn clean_uv_cache(uv: Option<&PathBuf>) -> Result<u64> {
+    let uv_path = locate_uv(uv).await.ok_or_else(|| {
+        error::user(
+            "Could not find uv",
+            "Please make sure uv is installed or pass `--uv`",
+        )
+    })?;
+    let cache_dir = tokio::process::Command::new(&uv_path)
+        .arg("cache")
+        .arg("dir")
+        .output()
+        .await
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()));
+    let size = cache_dir.as_deref().map(path_size).unwrap_or_default();
+    let output = tokio::process::Command::new(&uv_path)
+        .arg("cache")
+        .arg("clean")
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(error::system(
+            &format!(
+                "Failed to clean the uv cache: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ),
+            "",
+        ));
+    }
+    Ok(size)
 }
 
-pub async fn clean(_: Clean, global: GlobalArgs) -> Result<()> {
-    let project = read_pyproject(&global.project).await?;
+pub async fn clean(args: Clean, global: GlobalArgs) -> Result<()> {
+    let project = read_resolved_pyproject(&global.project).await?;
     let aqora = project.aqora().ok_or_else(|| {
         error::user(
             "No [tool.aqora] section found in pyproject.toml",
             "Please make sure you are in the correct directory",
         )
     })?;
+    let targets = if let Some(targets) = args.targets() {
+        let confirmation = global
+            .confirm()
+            .with_prompt(format!(
+                "This will remove {}. Do you want to continue?",
+                targets.describe().join(", ")
+            ))
+            .default(true)
+            .no_prompt_value(true)
+            .interact()?;
+        if !confirmation {
+            return Ok(());
+        }
+        targets
+    } else {
+        CleanTargets {
+            config: true,
+            venv: true,
+            notebooks: true,
+            generated: true,
+            ..Default::default()
+        }
+    };
+
+    let mut freed = 0;
     if let Some(template) = aqora
         .as_use_case()
         .and_then(|aqora| aqora.template.as_ref())
     {
-        clean_dir(template).await?;
+        freed += clean_dir(template, targets).await?;
+    }
+    freed += clean_dir(&global.project, targets).await?;
+    if targets.notebooks {
+        if let Some(generated_dir) = global.generated_dir(Some(&project)) {
+            // never remove the project itself if it was configured by mistake
+            let project_dir = dunce::canonicalize(&global.project)?;
+            let is_project_or_parent = dunce::canonicalize(&generated_dir)
+                .map_or(false, |generated_dir| {
+                    project_dir.starts_with(generated_dir)
+                });
+            if !is_project_or_parent {
+                freed += remove_path(&generated_dir, "generated code directory").await;
+            }
+        }
+    }
+    if targets.cache {
+        freed += clean_uv_cache(global.uv.as_ref()).await?;
+    }
+    if args.targets().is_some() {
+        println!("Freed {}", HumanBytes(freed));
     }
-    clean_dir(&global.project).await?;
     Ok(())
 }
@@ -1,12 +1,14 @@
 use crate::{
     colors::ColorChoiceExt,
     dialog::{Confirm, FuzzySelect},
-    dirs::{init_venv, opt_init_venv},
+    dirs::{create_tempdir, init_venv, opt_init_venv, pyproject_path, PYPROJECT_FILENAME},
     error::Result,
     graphql_client::graphql_url,
+    revert_file::CleanupTempDir,
 };
+use aqora_config::PyProject;
 use aqora_runner::python::{ColorChoice, LinkMode, PipOptions, PyEnv};
-use clap::Args;
+use clap::{parser::ValueSource, ArgMatches, Args, ValueEnum};
 use indicatif::ProgressBar;
 use serde::Serialize;
 use std::path::PathBuf;
@@ -33,13 +35,42 @@ pub struct GlobalArgs {
     pub url: String,
     #[arg(short, long, default_value = ".", global = true)]
     pub project: PathBuf,
+    #[arg(
+        long,
+        global = true,
+        conflicts_with = "project",
+        help = "Path to the pyproject.toml of the project (overrides --project)"
+    )]
+    pub config: Option<PathBuf>,
     #[arg(long, global = true)]
     pub uv: Option<PathBuf>,
     #[arg(long, global = true)]
     pub python: Option<String>,
     #[arg(long, global = true, default_value = "false")]
     pub ignore_venv_aqora: bool,
-    #[arg(long, default_value_t = *DEFAULT_PARALLELISM, global = true)]
+    #[arg(
+        long,
+        env = "AQORA_TMPDIR",
+        global = true,
+        help = "Directory used for temporary files such as archives and builds"
+    )]
+    pub tmp_dir: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        help = "Directory for code generated from notebooks when running tests, instead of __aqora__ next to each notebook"
+    )]
+    pub generated_dir: Option<PathBuf>,
+    /// Inputs are evaluated in a single Python interpreter, so this bounds how
+    /// many inputs are in flight at once rather than how many run in parallel:
+    /// only layers that await (e.g. on network I/O) overlap, while synchronous
+    /// Python code runs one input at a time under the GIL.
+    #[arg(
+        long,
+        default_value_t = *DEFAULT_PARALLELISM,
+        global = true,
+        help = "Maximum number of pipeline inputs evaluated concurrently. Only async layers benefit from values above 1"
+    )]
     pub max_concurrency: usize,
     #[arg(value_enum, long, default_value_t = ColorChoice::Auto, global = true)]
     pub color: ColorChoice,
@@ -48,6 +79,7 @@ pub struct GlobalArgs {
     #[arg(
         short = 'y',
         long = "no-prompt",
+        visible_aliases = ["yes", "non-interactive"],
         help = "Skip interactive dialogs and automatically confirm",
         default_value_t = false,
         global = true
@@ -60,6 +92,79 @@ impl GlobalArgs {
         if let Err(err) = Url::parse(&self.url) {
             return Err(format!("Invalid url: {}", err));
         }
+        if let Some(config) = self.config.as_ref() {
+            if !config.is_file() {
+                return Err(format!("Config file not found: {}", config.display()));
+            }
+            if config.file_name().and_then(|name| name.to_str()) != Some(PYPROJECT_FILENAME) {
+                return Err(format!(
+                    "Config file must be named {PYPROJECT_FILENAME}: {}",
+                    config.display()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Derives `project` from the parent directory of `config` if one was given
+    pub fn resolve_project(&mut self) {
+        if let Some(parent) = self.config.as_ref().and_then(|config| config.parent()) {
+            self.project = if parent.as_os_str().is_empty() {
+                PathBuf::from(".")
+            } else {
+                parent.to_path_buf()
+            };
+        }
+    }
+
+    /// Fills options that were left at their default value from
+    /// `[tool.aqora.cli]` in the project's pyproject.toml. A missing or
+    /// unreadable pyproject.toml is left for the command itself to report.
+    pub fn apply_project_defaults(&mut self, matches: &ArgMatches) -> Result<(), String> {
+        let Some(defaults) = std::fs::read_to_string(pyproject_path(&self.project))
+            .ok()
+            .and_then(|contents| PyProject::from_toml(contents).ok())
+            .and_then(|project| project.aqora().and_then(|aqora| aqora.cli()).cloned())
+        else {
+            return Ok(());
+        };
+        let is_default = |id: &str| {
+            matches
+                .value_source(id)
+                .map_or(true, |source| source == ValueSource::DefaultValue)
+        };
+        if let Some(max_concurrency) = defaults.max_concurrency {
+            if is_default("max_concurrency") {
+                self.max_concurrency = max_concurrency;
+            }
+        }
+        if let Some(color) = defaults.color.as_deref() {
+            if is_default("color") {
+                self.color = ColorChoice::from_str(color, true)
+                    .map_err(|err| format!("Invalid [tool.aqora.cli] color: {err}"))?;
+            }
+        }
+        if let Some(dep_link_mode) = defaults.dep_link_mode.as_deref() {
+            if is_default("dep_link_mode") {
+                self.dep_link_mode = LinkMode::from_str(dep_link_mode, true)
+                    .map_err(|err| format!("Invalid [tool.aqora.cli] dep_link_mode: {err}"))?;
+            }
+        }
+        if let Some(python) = defaults.python {
+            if is_default("python") {
+                self.python = Some(python);
+            }
+        }
+        if let Some(uv) = defaults.uv {
+            if is_default("uv") {
+                self.uv = Some(self.project.join(uv));
+            }
+        }
+        if let Some(no_prompt) = defaults.no_prompt {
+            if is_default("no_prompt") {
+                self.no_prompt = no_prompt;
+            }
+        }
         Ok(())
     }
 
@@ -105,6 +210,35 @@ impl GlobalArgs {
         .await
     }
 
+    /// The temporary directory given with `--tmp-dir`, falling back to
+    /// `tmp_dir` in `[tool.aqora]` (relative to the project)
+    pub fn tmp_dir(&self, project: Option<&PyProject>) -> Option<PathBuf> {
+        self.tmp_dir.clone().or_else(|| {
+            project
+                .and_then(|project| project.aqora())
+                .and_then(|aqora| aqora.tmp_dir())
+                .map(|tmp_dir| self.project.join(tmp_dir))
+        })
+    }
+
+    /// The directory given with `--generated-dir`, falling back to
+    /// `generated_dir` in `[tool.aqora]` (relative to the project)
+    pub fn generated_dir(&self, project: Option<&PyProject>) -> Option<PathBuf> {
+        self.generated_dir
+            .as_ref()
+            .map(|dir| self.project.join(dir))
+            .or_else(|| {
+                project
+                    .and_then(|project| project.aqora())
+                    .and_then(|aqora| aqora.generated_dir())
+                    .map(|dir| self.project.join(dir))
+            })
+    }
+
+    pub fn tempdir(&self, project: Option<&PyProject>, keep: bool) -> Result<CleanupTempDir> {
+        create_tempdir(self.tmp_dir(project), keep)
+    }
+
     pub fn confirm(&self) -> Confirm {
         Confirm::new()
             .with_theme(self.color.dialoguer())
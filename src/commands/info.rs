@@ -5,7 +5,7 @@ use crate::{
     graphql_client::GraphQLClient,
     manifest::manifest_version,
 };
-use clap::Args;
+use clap::{Args, Subcommand};
 use graphql_client::GraphQLQuery;
 use indicatif::ProgressBar;
 use pyo3::Python;
@@ -31,9 +31,26 @@ pub async fn get_viewer_info(global: &GlobalArgs) -> Result<viewer_info::ViewerI
 
 #[derive(Args, Debug, Serialize)]
 #[command(author, version, about)]
-pub struct Info;
+pub struct Info {
+    #[command(subcommand)]
+    pub command: Option<InfoCommand>,
+}
+
+#[derive(Subcommand, Debug, Serialize)]
+pub enum InfoCommand {
+    /// Print the JSON Schema of the [tool.aqora] section of pyproject.toml
+    #[command(hide = true)]
+    Schema,
+}
 
-pub async fn info(_: Info, global: GlobalArgs) -> Result<()> {
+pub async fn info(args: Info, global: GlobalArgs) -> Result<()> {
+    if let Some(InfoCommand::Schema) = args.command {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&aqora_config::json_schema())?
+        );
+        return Ok(());
+    }
     let _ = global.opt_init_venv(&ProgressBar::hidden()).await?;
     let python_prefix = Python::with_gil(|py| {
         py.import(pyo3::intern!(py, "sys"))
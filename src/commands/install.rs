@@ -1,9 +1,9 @@
 use crate::{
-    commands::GlobalArgs,
+    commands::{validate::validate_project, GlobalArgs},
     config::{write_project_config_default, ProjectConfig},
     dirs::{
         project_config_dir, project_data_dir, project_use_case_toml_path, project_venv_dir,
-        pyproject_path, read_pyproject,
+        pyproject_path, read_resolved_pyproject,
     },
     download::download_archive,
     error::{self, Result},
@@ -61,6 +61,11 @@ pub struct Install {
     #[arg(long, short)]
     pub upgrade: bool,
     pub competition: Option<String>,
+    #[arg(
+        long,
+        help = "Install the use case of a target configured under [tool.aqora.targets] (submissions only)"
+    )]
+    pub target: Option<String>,
 }
 
 pub async fn install_submission(
@@ -90,10 +95,16 @@ pub async fn install_submission(
             )
         })?;
 
+    let target = config.target(args.target.as_deref()).map_err(|err| {
+        error::user(
+            &err.to_string(),
+            "Check the targets under [tool.aqora.targets] in your pyproject.toml",
+        )
+    })?;
     let slug = args
         .competition
         .as_ref()
-        .or(config.competition.as_ref())
+        .or(target.competition.as_ref())
         .ok_or_else(|| {
             error::user(
                 "No competition provided",
@@ -214,9 +225,11 @@ pub async fn install_submission(
             .clone();
 
         download_pb.set_message("Downloading use case data");
+        let tmp_dir = global.tmp_dir(Some(&project));
         let download_fut = download_archive(
             use_case_data_url,
             project_data_dir(&global.project),
+            tmp_dir.as_deref(),
             &download_pb,
         )
         .inspect(|res| {
@@ -276,6 +289,12 @@ pub async fn install_submission(
 }
 
 pub async fn install_use_case(args: Install, global: GlobalArgs, project: PyProject) -> Result<()> {
+    if args.target.is_some() {
+        return Err(error::user(
+            "--target is only supported for submissions",
+            "Remove --target and try again",
+        ));
+    }
     let m = MultiProgress::new();
 
     let use_case = project
@@ -364,13 +383,28 @@ pub async fn install_use_case(args: Install, global: GlobalArgs, project: PyProj
 }
 
 pub async fn install(args: Install, global: GlobalArgs) -> Result<()> {
-    let project = read_pyproject(&global.project).await?;
+    let project = read_resolved_pyproject(&global.project).await?;
     let aqora = project.aqora().ok_or_else(|| {
         error::user(
             "No [tool.aqora] section found in pyproject.toml",
             "Please make sure you are in the correct directory",
         )
     })?;
+    let problems = validate_project(&global.project, &project);
+    if !problems.is_empty() {
+        return Err(error::user(
+            &format!(
+                "Found {} problem(s) in the project:\n{}",
+                problems.len(),
+                problems
+                    .iter()
+                    .map(|problem| format!("  - {problem}"))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ),
+            "Fix the problems above and try again",
+        ));
+    }
     if aqora.is_submission() {
         install_submission(args, global, project).await
     } else {
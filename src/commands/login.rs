@@ -1,7 +1,8 @@
 use crate::{
-    commands::GlobalArgs,
+    commands::{info::get_viewer_info, GlobalArgs},
     credentials::{get_credentials, with_locked_credentials, Credentials},
     error::{self, Result},
+    graphql_client::GraphQLClient,
     progress_bar::default_spinner,
     shutdown::shutdown_signal,
 };
@@ -18,7 +19,7 @@ use futures::prelude::*;
 use graphql_client::GraphQLQuery;
 use indicatif::{MultiProgress, ProgressBar};
 use serde::{Deserialize, Serialize};
-use std::io::{BufRead, Write};
+use std::io::{BufRead, Read, Write};
 use std::{future::IntoFuture, sync::Arc};
 use tokio::{
     net::TcpListener,
@@ -27,12 +28,73 @@ use tokio::{
 use url::Url;
 
 const CLIENT_ID_PREFIX: &str = "localhost-";
+const TOKEN_ENV_VAR: &str = "AQORA_TOKEN";
+/// Checking the connection should be quick, so don't leave the user waiting
+const PING_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15);
 
 #[derive(Args, Default, Debug, Serialize)]
 #[command(author, version, about)]
 pub struct Login {
     #[arg(long, short, help = "Force login without a browser")]
     interactive: bool,
+    #[arg(
+        long,
+        conflicts_with = "interactive",
+        help = "Check the connection and current login without logging in"
+    )]
+    check: bool,
+    #[arg(
+        long,
+        group = "token",
+        conflicts_with_all = ["interactive", "check", "token_env"],
+        help = "Log in with a refresh token read from stdin instead of a browser"
+    )]
+    token_stdin: bool,
+    #[arg(
+        long,
+        group = "token",
+        conflicts_with_all = ["interactive", "check"],
+        help = "Log in with a refresh token read from the AQORA_TOKEN environment variable"
+    )]
+    token_env: bool,
+    #[arg(
+        long,
+        requires = "token",
+        help = "OAuth client the token was issued to (defaults to this machine's client)"
+    )]
+    client_id: Option<String>,
+}
+
+impl Login {
+    fn read_token(&self) -> Result<Option<String>> {
+        let token = if self.token_stdin {
+            let mut token = String::new();
+            std::io::stdin().read_to_string(&mut token).map_err(|err| {
+                error::user(
+                    &format!("Could not read token from stdin: {err}"),
+                    "Pipe the token into the command, e.g. `echo $TOKEN | aqora login --token-stdin`",
+                )
+            })?;
+            token
+        } else if self.token_env {
+            std::env::var(TOKEN_ENV_VAR).map_err(|_| {
+                error::user(
+                    &format!("{TOKEN_ENV_VAR} is not set"),
+                    &format!("Set {TOKEN_ENV_VAR} to your token and try again"),
+                )
+            })?
+        } else {
+            return Ok(None);
+        };
+        let token = token.trim();
+        if token.is_empty() {
+            return Err(error::user(
+                "The token is empty",
+                "Provide a non-empty token and try again",
+            ));
+        }
+        Ok(Some(token.to_string()))
+    }
 }
 
 fn client_id() -> String {
@@ -381,7 +443,75 @@ async fn do_login(args: Login, global: GlobalArgs, progress: ProgressBar) -> Res
     .await
 }
 
+/// Exchanges the token for fresh credentials, which both validates it and
+/// avoids storing a token that may also be used elsewhere
+async fn login_with_token(
+    global: GlobalArgs,
+    token: String,
+    client_id: String,
+    progress: ProgressBar,
+) -> Result<()> {
+    let url = global.aqora_url()?;
+    progress.set_message("Logging in...");
+    // Refreshing validates the token, and unlike a GraphQLClient it does not
+    // touch the stored credentials, which may have expired
+    let credentials = Credentials {
+        client_id,
+        access_token: String::new(),
+        refresh_token: token,
+        expires_at: Utc::now(),
+    }
+    .refresh(&url)
+    .await
+    .and_then(|credentials| credentials.ok_or_else(|| error::system("No credentials issued", "")))
+    .map_err(|err| {
+        error::auth(
+            &format!("The token was not accepted: {err}"),
+            "Check that the token is valid and was issued to the given client",
+        )
+    })?;
+    with_locked_credentials(|file| {
+        async move {
+            file.credentials.insert(url, credentials);
+            Ok(())
+        }
+        .boxed()
+    })
+    .await?;
+    progress.finish_with_message("Logged in successfully!");
+    Ok(())
+}
+
+async fn check_connection(global: GlobalArgs) -> Result<()> {
+    let url = global.aqora_url()?;
+    let client = GraphQLClient::new(url.clone())
+        .await?
+        .with_timeout(PING_TIMEOUT);
+    let ping = client.ping().await?;
+    println!(
+        "Connected to {url} (server {}) in {}ms",
+        ping.server_version,
+        ping.latency.as_millis()
+    );
+    if get_credentials(url).await?.is_none() {
+        return Err(error::auth(
+            "Not logged in",
+            "Run `aqora login` to connect your account",
+        ));
+    }
+    let viewer = get_viewer_info(&global).await?;
+    println!("Logged in as {}", viewer.username);
+    Ok(())
+}
+
 pub async fn login(args: Login, global: GlobalArgs) -> Result<()> {
+    if args.check {
+        return check_connection(global).await;
+    }
+    if let Some(token) = args.read_token()? {
+        let client_id = args.client_id.unwrap_or_else(client_id);
+        return login_with_token(global, token, client_id, default_spinner()).await;
+    }
     do_login(args, global, default_spinner()).await
 }
 
@@ -0,0 +1,40 @@
+use crate::{commands::GlobalArgs, credentials::with_locked_credentials, error::Result};
+use clap::Args;
+use futures::prelude::*;
+use serde::Serialize;
+
+#[derive(Args, Debug, Serialize)]
+#[command(author, version, about)]
+pub struct Logout {
+    #[arg(
+        long,
+        help = "Remove the stored credentials of every server, not just the current one"
+    )]
+    all: bool,
+}
+
+pub async fn logout(args: Logout, global: GlobalArgs) -> Result<()> {
+    let url = global.aqora_url()?;
+    let all = args.all;
+    let removed_url = url.clone();
+    let removed = with_locked_credentials(move |file| {
+        async move {
+            Ok(if all {
+                let removed = file.credentials.len();
+                file.credentials.clear();
+                removed
+            } else {
+                usize::from(file.credentials.remove(&removed_url).is_some())
+            })
+        }
+        .boxed()
+    })
+    .await?;
+    match (all, removed) {
+        (true, 0) => println!("No stored credentials to remove"),
+        (true, removed) => println!("Logged out of {removed} server(s)"),
+        (false, 0) => println!("Not logged in to {url}"),
+        (false, _) => println!("Logged out of {url}"),
+    }
+    Ok(())
+}
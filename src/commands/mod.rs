@@ -5,6 +5,7 @@ mod info;
 mod install;
 mod lab;
 mod login;
+mod logout;
 mod new;
 mod python;
 mod remove;
@@ -12,7 +13,9 @@ mod shell;
 mod template;
 mod test;
 mod upload;
+mod validate;
 mod version;
+mod whoami;
 
 use serde::Serialize;
 
@@ -24,6 +27,7 @@ use info::{info, Info};
 use install::{install, Install};
 use lab::{lab, Lab};
 use login::{login, Login};
+use logout::{logout, Logout};
 use new::{new, New};
 use python::{python, Python};
 use remove::{remove, Remove};
@@ -31,9 +35,11 @@ use shell::{shell, Shell};
 use template::{template, Template};
 use test::{test, Test};
 use upload::{upload, Upload};
+use validate::{validate, Validate};
+use whoami::{whoami, Whoami};
 
 use crate::{
-    colors::ColorChoiceExt, commands::version::version, revert_file::revert_all,
+    colors::ColorChoiceExt, commands::version::version, error::ErrorClass, revert_file::revert_all,
     shutdown::shutdown_signal,
 };
 use clap::{CommandFactory, Parser, Subcommand};
@@ -55,6 +61,8 @@ pub enum Commands {
         args: New,
     },
     Login(Login),
+    Logout(Logout),
+    Whoami(Whoami),
     Python(Python),
     Shell(Shell),
     Test(Test),
@@ -63,6 +71,8 @@ pub enum Commands {
     Clean(Clean),
     Add(Add),
     Remove(Remove),
+    #[command(alias = "check")]
+    Validate(Validate),
     #[command(hide = true)]
     Info(Info),
     Lab(Lab),
@@ -81,6 +91,8 @@ impl Cli {
                 Commands::Install(args) => install(args, global).await,
                 Commands::New { args } => new(args, global).await,
                 Commands::Login(args) => login(args, global).await,
+                Commands::Logout(args) => logout(args, global).await,
+                Commands::Whoami(args) => whoami(args, global).await,
                 Commands::Python(args) => python(args, global).await,
                 Commands::Shell(args) => shell(args, global).await,
                 Commands::Test(args) => test(args, global).await,
@@ -91,6 +103,7 @@ impl Cli {
                 Commands::Info(args) => info(args, global).await,
                 Commands::Add(args) => add(args, global).await,
                 Commands::Remove(args) => remove(args, global).await,
+                Commands::Validate(args) => validate(args, global).await,
             }
         };
         tokio::select! {
@@ -103,7 +116,9 @@ impl Cli {
         }
     }
 
-    pub async fn run(self) -> bool {
+    /// Runs the command and returns the process exit code: `0` on success,
+    /// otherwise the code of the failure's [`ErrorClass`]
+    pub async fn run(self) -> u8 {
         let command_context =
             sentry::protocol::Context::Other(std::collections::BTreeMap::from([(
                 "args".into(),
@@ -131,9 +146,9 @@ impl Cli {
             } else {
                 tracing::error!(error = &run_error as &dyn std::error::Error, "{run_error}");
             }
-            false
+            ErrorClass::of(&run_error).exit_code()
         } else {
-            true
+            0
         }
     }
 }
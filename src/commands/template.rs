@@ -160,7 +160,8 @@ pub async fn template(args: Template, global: GlobalArgs) -> Result<()> {
     };
 
     pb.set_message("Downloading competition template...");
-    match download_archive(download_url, &destination, &pb).await {
+    let tmp_dir = global.tmp_dir(None);
+    match download_archive(download_url, &destination, tmp_dir.as_deref(), &pb).await {
         Ok(_) => {
             init_repository(&pb, &destination, None)
                 .inspect_err(|e| {
@@ -228,7 +229,7 @@ pub async fn template(args: Template, global: GlobalArgs) -> Result<()> {
             project: destination.clone(),
             ..global
         };
-        clean(Clean, clean_global).await?;
+        clean(Clean::default(), clean_global).await?;
     } else {
         let install_global = GlobalArgs {
             project: destination.clone(),
@@ -238,6 +239,7 @@ pub async fn template(args: Template, global: GlobalArgs) -> Result<()> {
             Install {
                 competition: Some(args.competition),
                 upgrade: true,
+                target: None,
             },
             install_global,
         )
@@ -1,19 +1,22 @@
 use crate::{
-    commands::GlobalArgs,
+    commands::{validate::OutputFormat, GlobalArgs},
     config::read_project_config,
     dirs::{
         project_data_dir, project_last_run_dir, project_last_run_result,
-        project_use_case_toml_path, read_pyproject,
+        project_use_case_toml_path, read_resolved_pyproject,
     },
     error::{self, Result},
-    evaluate::evaluate,
-    ipynb::{convert_submission_notebooks, convert_use_case_notebooks},
+    evaluate::{evaluate, Watchdog},
+    github,
+    ipynb::{convert_submission_notebooks, convert_use_case_notebooks, ConvertOptions},
     print::wrap_python_output,
     python::LastRunResult,
 };
-use aqora_config::{AqoraUseCaseConfig, PyProject};
+use aqora_config::{AqoraSubmissionConfig, AqoraUseCaseConfig, PyProject};
 use aqora_runner::{
-    pipeline::{EvaluateAllInfo, EvaluateInputInfo, EvaluationError, Pipeline, PipelineConfig},
+    pipeline::{
+        Aggregate, EvaluateAllInfo, EvaluateInputInfo, EvaluationError, Pipeline, PipelineConfig,
+    },
     python::PyEnv,
 };
 use clap::Args;
@@ -22,19 +25,312 @@ use indicatif::{MultiProgress, ProgressBar};
 use owo_colors::{OwoColorize, Stream as OwoStream};
 use pyo3::prelude::*;
 use pyo3::{exceptions::PyException, Python};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     path::{Path, PathBuf},
     pin::Pin,
     sync::{atomic::AtomicU32, Arc},
+    time::Duration,
 };
 
-#[derive(Args, Debug, Clone, Serialize)]
+#[derive(Args, Debug, Clone, Default, Serialize)]
 #[command(author, version, about)]
 pub struct Test {
-    #[arg(short, long)]
+    #[arg(
+        short,
+        long,
+        help = "Tests to run: `name`, `name::index`, `name::*` or a glob such as `smoke_*`"
+    )]
     pub test: Vec<String>,
+    #[arg(
+        long,
+        help = "Re-run a single input from the last run without capturing Python output"
+    )]
+    pub replay: Option<usize>,
+    #[arg(
+        long = "env",
+        value_name = "KEY=VALUE",
+        value_parser = parse_env_var,
+        help = "Set an environment variable for the pipeline (can be repeated)"
+    )]
+    #[serde(skip_serializing)]
+    pub env: Vec<(String, String)>,
+    #[arg(
+        long,
+        visible_alias = "keep-going-across-use-case-tests",
+        help = "Run every use case test even if some fail and print a summary"
+    )]
+    pub keep_going: bool,
+    #[arg(value_enum, long, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+    #[arg(
+        value_enum,
+        long,
+        help = "Aggregate the metrics with a built-in aggregator instead of the configured one"
+    )]
+    pub aggregate: Option<Aggregate>,
+    #[arg(
+        long,
+        conflicts_with = "replay",
+        help = "Read the inputs from a last run directory or a msgpack sequence of items with a pickled `input` instead of the generator"
+    )]
+    pub inputs: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Write the score to the GitHub Actions step outputs and job summary when run in GitHub Actions"
+    )]
+    pub github_output: bool,
+    #[arg(
+        long,
+        value_name = "LAYER",
+        conflicts_with = "replay",
+        help = "Bypass the transform of a layer, passing its input through unchanged (can be repeated)"
+    )]
+    pub skip_layer: Vec<String>,
+    #[arg(
+        long,
+        value_name = "LAYER",
+        conflicts_with = "replay",
+        help = "Only run the pipeline up to and including the given layer"
+    )]
+    pub only_through: Option<String>,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "Abort the run if it takes longer than this. Results of completed inputs are kept"
+    )]
+    pub max_runtime: Option<u64>,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 60,
+        help = "Warn when no input completes within this many seconds (0 to disable)"
+    )]
+    pub heartbeat: u64,
+    #[arg(
+        long,
+        env = "AQORA_NO_SHELL_MAGICS",
+        help = "Refuse to run notebooks containing `!` commands or magics that may run shell commands, such as `%rm` or `%%bash`"
+    )]
+    pub no_shell_magics: bool,
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with = "replay",
+        help = "Write the metric of each input to a CSV file for analysis"
+    )]
+    pub metrics_out: Option<PathBuf>,
+}
+
+impl Test {
+    /// Whether the layers run differ from the configured pipeline
+    fn changes_layers(&self) -> bool {
+        !self.skip_layer.is_empty() || self.only_through.is_some()
+    }
+
+    fn select_layers(&self, use_case: &mut AqoraUseCaseConfig) -> Result<()> {
+        use_case
+            .select_layers(&self.skip_layer, self.only_through.as_deref())
+            .map_err(|err| {
+                error::user(
+                    &format!("Failed to select layers: {err}"),
+                    "Check the layer names in the pipeline configuration and try again",
+                )
+            })
+    }
+
+    fn convert_options(&self, global: &GlobalArgs, project: &PyProject) -> ConvertOptions {
+        ConvertOptions {
+            no_shell_magics: self.no_shell_magics,
+            generated_dir: global.generated_dir(Some(project)),
+        }
+    }
+
+    fn watchdog(&self) -> Watchdog {
+        Watchdog {
+            heartbeat: (self.heartbeat > 0).then(|| Duration::from_secs(self.heartbeat)),
+            max_runtime: self.max_runtime.map(Duration::from_secs),
+        }
+    }
+}
+
+/// One row of `--metrics-out`: a metric of the last layer with a metric for
+/// an input, or the error if the input has no metric
+struct MetricRow {
+    test: Option<String>,
+    index: usize,
+    layer: String,
+    metric: String,
+    error: String,
+}
+
+/// Reads the metrics of the inputs recorded in `last_run_dir`. `tests` selects
+/// inputs by their 1-based index, all recorded inputs are read if it is empty.
+async fn read_metrics(
+    last_run_dir: &Path,
+    layers: &[String],
+    tests: &[usize],
+    test: Option<&str>,
+) -> Result<Vec<MetricRow>> {
+    let read_error = |err: std::io::Error| {
+        error::user(
+            &format!(
+                "Failed to read metrics from {}: {err}",
+                last_run_dir.display()
+            ),
+            "Check the above error and try again",
+        )
+    };
+    let mut indexes = tests.to_vec();
+    if indexes.is_empty() {
+        for entry in std::fs::read_dir(last_run_dir).map_err(read_error)? {
+            let path = entry.map_err(read_error)?.path();
+            if path.extension().map_or(false, |ext| ext == "msgpack") {
+                if let Some(index) = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<usize>().ok())
+                {
+                    indexes.push(index + 1);
+                }
+            }
+        }
+        indexes.sort_unstable();
+    }
+    let mut rows = Vec::new();
+    for index in indexes {
+        let item = match last_run_items(last_run_dir, vec![index]).next().await {
+            Some(Ok((_, item))) => item,
+            Some(Err((_, err))) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Some(Err((_, err))) => return Err(read_error(err)),
+            None => continue,
+        };
+        let error = item.error.map(|err| err.to_string()).unwrap_or_default();
+        let metrics = layers.iter().rev().find_map(|layer| {
+            item.result
+                .get(layer)
+                .filter(|evaluations| evaluations.iter().any(|eval| eval.metric.is_some()))
+                .map(|evaluations| (layer, evaluations))
+        });
+        let Some((layer, evaluations)) = metrics else {
+            rows.push(MetricRow {
+                test: test.map(str::to_string),
+                index,
+                layer: String::new(),
+                metric: String::new(),
+                error,
+            });
+            continue;
+        };
+        for metric in evaluations.iter().filter_map(|eval| eval.metric.as_ref()) {
+            rows.push(MetricRow {
+                test: test.map(str::to_string),
+                index,
+                layer: layer.clone(),
+                metric: metric.to_string(),
+                error: error.clone(),
+            });
+        }
+    }
+    Ok(rows)
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn write_metrics(path: &Path, rows: &[MetricRow]) -> Result<()> {
+    let with_test = rows.iter().any(|row| row.test.is_some());
+    let mut out = if with_test {
+        "test,index,layer,metric,error\n"
+    } else {
+        "index,layer,metric,error\n"
+    }
+    .to_string();
+    for row in rows {
+        let mut fields = Vec::with_capacity(5);
+        if with_test {
+            fields.push(csv_field(row.test.as_deref().unwrap_or_default()));
+        }
+        fields.extend([
+            row.index.to_string(),
+            csv_field(&row.layer),
+            csv_field(&row.metric),
+            csv_field(&row.error),
+        ]);
+        out.push_str(&fields.join(","));
+        out.push('\n');
+    }
+    std::fs::write(path, out).map_err(|e| {
+        error::user(
+            &format!("Failed to write metrics to {}: {e}", path.display()),
+            &format!(
+                "Make sure you have permissions to write to {}",
+                path.display()
+            ),
+        )
+    })
+}
+
+fn layer_names(use_case: &AqoraUseCaseConfig) -> Vec<String> {
+    use_case
+        .layers
+        .iter()
+        .map(|layer| layer.name.clone())
+        .collect()
+}
+
+fn parse_env_var(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once('=')
+        .filter(|(key, _)| !key.is_empty())
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| "expected KEY=VALUE".to_string())
+}
+
+/// Loads the project `.env` file and the `--env` overrides into the Python
+/// environment. Values may be secrets so they are never logged.
+fn load_python_env(project_dir: &Path, overrides: &[(String, String)]) -> Result<()> {
+    let mut vars = Vec::new();
+    let dotenv_path = project_dir.join(".env");
+    if dotenv_path.exists() {
+        let read_error = |err: dotenvy::Error| {
+            let reason = match err {
+                dotenvy::Error::LineParse(_, index) => format!("invalid syntax at {index}"),
+                err => err.to_string(),
+            };
+            error::user(
+                &format!("Failed to read {}: {reason}", dotenv_path.display()),
+                "Check the syntax of your .env file",
+            )
+        };
+        for item in dotenvy::from_path_iter(&dotenv_path).map_err(read_error)? {
+            vars.push(item.map_err(read_error)?);
+        }
+    }
+    vars.extend(overrides.iter().cloned());
+    if vars.is_empty() {
+        return Ok(());
+    }
+    tracing::debug!(
+        keys = ?vars.iter().map(|(key, _)| key).collect::<Vec<_>>(),
+        "Setting pipeline environment variables"
+    );
+    Python::with_gil(|py| {
+        let environ = py
+            .import(pyo3::intern!(py, "os"))?
+            .getattr(pyo3::intern!(py, "environ"))?;
+        for (key, value) in vars {
+            environ.set_item(key, value)?;
+        }
+        PyResult::Ok(())
+    })
+    .map_err(|err| error::system(&format!("Failed to set environment variables: {err}"), ""))
 }
 
 fn last_run_items(
@@ -67,12 +363,159 @@ fn last_run_items(
     })
 }
 
+#[derive(Deserialize)]
+struct InputItem {
+    #[serde(with = "aqora_runner::python::serde_pickle")]
+    input: PyObject,
+}
+
+/// Reads the inputs recorded in a last run directory, indexed by the
+/// `{index}.msgpack` file they were written to. Inputs that failed to generate
+/// have nothing recorded and are `None`.
+fn read_last_run_inputs(last_run_dir: &Path) -> Result<Vec<Option<PyObject>>> {
+    let read_error = |err: std::io::Error| {
+        error::user(
+            &format!(
+                "Failed to read inputs from {}: {err}",
+                last_run_dir.display()
+            ),
+            "Make sure the directory exists and is readable",
+        )
+    };
+    let mut inputs = Vec::new();
+    for entry in std::fs::read_dir(last_run_dir).map_err(read_error)? {
+        let path = entry.map_err(read_error)?.path();
+        if path.extension().map_or(true, |ext| ext != "msgpack") {
+            continue;
+        }
+        let Some(index) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<usize>().ok())
+        else {
+            continue;
+        };
+        let file = std::fs::File::open(&path).map_err(read_error)?;
+        let item: EvaluateInputInfo = rmp_serde::from_read(file).map_err(|e| {
+            error::user(
+                &format!("Failed to parse inputs from {}: {e}", path.display()),
+                "The directory must be a last run directory written by `aqora test`",
+            )
+        })?;
+        if inputs.len() <= index {
+            inputs.resize_with(index + 1, || None);
+        }
+        inputs[index] = item.input;
+    }
+    Ok(inputs)
+}
+
+/// Inputs passed with `--inputs`, loaded into memory before the last run
+/// directory is cleared since they may be read from it
+struct Inputs {
+    path: PathBuf,
+    inputs: Vec<Option<PyObject>>,
+}
+
+impl Inputs {
+    /// Loads the inputs from either a last run directory or a msgpack sequence
+    /// of items with a pickled `input`
+    fn read(path: &Path) -> Result<Self> {
+        let inputs = if path.is_dir() {
+            read_last_run_inputs(path)?
+        } else {
+            let file = std::fs::File::open(path).map_err(|e| {
+                error::user(
+                    &format!("Failed to read inputs from {}: {e}", path.display()),
+                    "Make sure the file exists and is readable",
+                )
+            })?;
+            let items: Vec<InputItem> = rmp_serde::from_read(file).map_err(|e| {
+                error::user(
+                    &format!("Failed to parse inputs from {}: {e}", path.display()),
+                    "The file must be a msgpack sequence of items with a pickled `input`",
+                )
+            })?;
+            items
+                .into_iter()
+                .map(|item| Some(item.input))
+                .collect::<Vec<_>>()
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            inputs,
+        })
+    }
+
+    /// Selects inputs by their 1-based index, all inputs are returned if
+    /// `tests` is empty
+    fn select(&self, tests: &[usize]) -> Result<Vec<(usize, PyResult<PyObject>)>> {
+        if tests.is_empty() {
+            return Ok(self
+                .inputs
+                .iter()
+                .enumerate()
+                .filter_map(|(index, input)| Some((index, Ok(input.clone()?))))
+                .collect());
+        }
+        let mut selected = Vec::with_capacity(tests.len());
+        for &index in tests {
+            let input = index
+                .checked_sub(1)
+                .filter(|i| !selected.iter().any(|(taken, _)| taken == i))
+                .and_then(|i| Some((i, Ok(self.inputs.get(i)?.clone()?))))
+                .ok_or_else(|| {
+                    error::user(
+                        &format!("Input {index} not found in {}", self.path.display()),
+                        "Input indexes start from 1 and may only be selected once",
+                    )
+                })?;
+            selected.push(input);
+        }
+        Ok(selected)
+    }
+}
+
+/// Removes the results of the previous run from `last_run_dir`: all of them
+/// when `clear` is set, otherwise only the overall result so that selected
+/// inputs can still be replayed from it
+async fn reset_last_run_dir(
+    last_run_dir: &Path,
+    last_run_result_file: Option<&Path>,
+    clear: bool,
+) -> Result<()> {
+    let write_error = |e: std::io::Error| {
+        error::user(
+            &format!("Failed to write to {}: {}", last_run_dir.display(), e),
+            &format!(
+                "Make sure you have permissions to write to {}",
+                last_run_dir.display()
+            ),
+        )
+    };
+    if clear {
+        if last_run_dir.exists() {
+            tokio::fs::remove_dir_all(last_run_dir)
+                .await
+                .map_err(write_error)?;
+        }
+    } else if let Some(file) = last_run_result_file.filter(|file| file.exists()) {
+        tokio::fs::remove_file(file).await.map_err(write_error)?;
+    }
+    tokio::fs::create_dir_all(last_run_dir)
+        .await
+        .map_err(write_error)
+}
+
 struct RunPipelineConfig {
     use_case: AqoraUseCaseConfig,
     pipeline_config: PipelineConfig,
     last_run_dir: PathBuf,
     tests: Vec<usize>,
     max_concurrency: usize,
+    aggregate: Option<Aggregate>,
+    inputs: Option<Vec<(usize, PyResult<PyObject>)>>,
+    watchdog: Watchdog,
 }
 
 async fn do_run_pipeline(
@@ -104,7 +547,12 @@ async fn do_run_pipeline(
 
     pb.set_message("Running tests...");
 
-    let (num_inputs, generator) = if config.tests.is_empty() {
+    let (num_inputs, generator) = if let Some(inputs) = config.inputs {
+        (
+            Arc::new(AtomicU32::new(inputs.len() as u32)),
+            Box::pin(futures::stream::iter(inputs)) as _,
+        )
+    } else if config.tests.is_empty() {
         match pipeline.generator() {
             Ok(generator) => {
                 let num_inputs = Arc::new(AtomicU32::new(0));
@@ -157,16 +605,29 @@ async fn do_run_pipeline(
         )
     };
 
-    let aggregated = pipeline
-        .aggregate(evaluate(
-            pipeline.evaluator(),
-            generator,
-            config.max_concurrency,
-            Some(config.last_run_dir),
-            name.map(|name| name.to_string()),
-            pb.clone(),
-        ))
-        .await;
+    let results = evaluate(
+        pipeline.evaluator(),
+        generator,
+        config.max_concurrency,
+        Some(config.last_run_dir),
+        name.map(|name| name.to_string()),
+        pb.clone(),
+    );
+    let started = num_inputs.clone();
+    let max_concurrency = config.max_concurrency;
+    let results = config.watchdog.watch(
+        results,
+        move |completed| {
+            let started = started.load(std::sync::atomic::Ordering::Relaxed) as usize;
+            started.saturating_sub(completed).min(max_concurrency)
+        },
+        pb.clone(),
+    );
+    let aggregated = if let Some(aggregate) = config.aggregate {
+        pipeline.aggregate_with(results, aggregate).await
+    } else {
+        pipeline.aggregate(results).await
+    };
 
     Ok((
         num_inputs.load(std::sync::atomic::Ordering::Relaxed),
@@ -208,19 +669,86 @@ fn run_pipeline(
     )
 }
 
-pub async fn run_submission_tests(
-    m: &MultiProgress,
-    global: &GlobalArgs,
-    project: &PyProject,
-    tests: Vec<String>,
-) -> Result<()> {
-    let submission = project
-        .aqora()
-        .and_then(|aqora| aqora.as_submission())
-        .ok_or_else(|| error::user("Submission config is not valid", ""))?;
+struct ReplayConfig {
+    use_case: AqoraUseCaseConfig,
+    pipeline_config: PipelineConfig,
+    last_run_dir: PathBuf,
+    index: usize,
+}
 
-    let project_config = read_project_config(&global.project).await?;
+async fn do_replay(env: PyEnv, config: ReplayConfig) -> Result<()> {
+    let index = config.index;
+    let pipeline =
+        Pipeline::import(&env, &config.use_case, config.pipeline_config).map_err(|err| {
+            Python::with_gil(|py| err.print_and_set_sys_last_vars(py));
+            error::user(
+                "Failed to import pipeline",
+                "Check the above error and try again",
+            )
+        })?;
 
+    let (_, item) = last_run_items(&config.last_run_dir, vec![index])
+        .next()
+        .await
+        .ok_or_else(|| error::system("No input to replay", "This is a bug, please report it"))?
+        .map_err(|(index, err)| {
+            error::user(
+                &format!("Failed to read last run data for {index}: {err}"),
+                "Run `aqora test` first to record the inputs",
+            )
+        })?;
+
+    let Some(input) = item.input else {
+        if let Some(EvaluationError::Python(err)) = item.error {
+            Python::with_gil(|py| err.print_and_set_sys_last_vars(py));
+        }
+        return Err(error::user(
+            &format!("Input {index} could not be generated and cannot be replayed"),
+            "Check the above error and try again",
+        ));
+    };
+
+    match pipeline.evaluator().evaluate(input, None).await {
+        Ok(_) => {
+            println!(
+                "{} Success",
+                format!("[{index} OK]").if_supports_color(OwoStream::Stdout, |text| text.green())
+            );
+            Ok(())
+        }
+        Err((_, EvaluationError::Python(err))) => {
+            Python::with_gil(|py| err.print_and_set_sys_last_vars(py));
+            Err(error::user(
+                &format!("Failed to replay input {index}"),
+                "Check the above error and try again",
+            ))
+        }
+        Err((_, err)) => Err(error::user(
+            &format!("Failed to replay input {index}: {err}"),
+            "Check the pipeline configuration and try again",
+        )),
+    }
+}
+
+/// Re-runs a single input recorded in the last run. Python output is not
+/// captured so that tracebacks and debuggers (e.g. `breakpoint()`) work as is.
+fn replay(env: &PyEnv, config: ReplayConfig) -> Result<()> {
+    let run_env = env.clone();
+    match pyo3::Python::with_gil(move |py| {
+        pyo3_asyncio::tokio::run(py, async move { Ok(do_replay(run_env, config).await) })
+    }) {
+        Ok(res) => res,
+        Err(err) => {
+            Python::with_gil(|py| err.print_and_set_sys_last_vars(py));
+            Err(error::system(
+                "Failed to replay input",
+                "Check the above error and try again",
+            ))
+        }
+    }
+}
+
+async fn read_installed_use_case(global: &GlobalArgs) -> Result<(PyProject, AqoraUseCaseConfig)> {
     let use_case_toml_path = project_use_case_toml_path(&global.project);
     let data_path = project_data_dir(&global.project);
     if !use_case_toml_path.exists() || !data_path.exists() {
@@ -240,53 +768,64 @@ pub async fn run_submission_tests(
     let use_case = use_case_toml
         .aqora()
         .and_then(|aqora| aqora.as_use_case())
+        .cloned()
         .ok_or_else(|| {
             error::system(
                 "Use case config is not valid",
                 "Check with your competition provider",
             )
         })?;
+    Ok((use_case_toml, use_case))
+}
+
+async fn submission_use_case(
+    env: &PyEnv,
+    use_case: &AqoraUseCaseConfig,
+    submission: &AqoraSubmissionConfig,
+    options: ConvertOptions,
+) -> Result<AqoraUseCaseConfig> {
+    let mut use_case = use_case.clone();
+    let mut submission = submission.clone();
+    convert_submission_notebooks(env, &mut submission, options).await?;
+    if let Err(err) = use_case.replace_refs(&submission.refs) {
+        return Err(error::system(
+            &format!("Failed to import pipeline: {err}"),
+            "Check the pipeline configuration and try again",
+        ));
+    }
+    Ok(use_case)
+}
+
+pub async fn run_submission_tests(
+    m: &MultiProgress,
+    global: &GlobalArgs,
+    project: &PyProject,
+    args: &Test,
+) -> Result<()> {
+    let submission = project
+        .aqora()
+        .and_then(|aqora| aqora.as_submission())
+        .ok_or_else(|| error::user("Submission config is not valid", ""))?;
+
+    let project_config = read_project_config(&global.project).await?;
+
+    let (use_case_toml, use_case) = read_installed_use_case(global).await?;
 
     let last_run_dir = project_last_run_dir(&global.project);
     let last_run_result_file = project_last_run_result(&global.project);
-    if tests.is_empty() {
-        if last_run_dir.exists() {
-            tokio::fs::remove_dir_all(&last_run_dir)
-                .await
-                .map_err(|e| {
-                    error::user(
-                        &format!("Failed to write to {}: {}", last_run_dir.display(), e),
-                        &format!(
-                            "Make sure you have permissions to write to {}",
-                            last_run_dir.display()
-                        ),
-                    )
-                })?;
-        }
-    } else if last_run_result_file.exists() {
-        tokio::fs::remove_file(&last_run_result_file)
-            .await
-            .map_err(|e| {
+
+    let tests = args
+        .test
+        .iter()
+        .map(|test| {
+            test.parse::<usize>().map_err(|_| {
                 error::user(
-                    &format!("Failed to write to {}: {}", last_run_dir.display(), e),
-                    &format!(
-                        "Make sure you have permissions to write to {}",
-                        last_run_dir.display()
-                    ),
+                    &format!("Invalid test index: {test}"),
+                    "Please provide a valid test index",
                 )
-            })?;
-    }
-    tokio::fs::create_dir_all(&last_run_dir)
-        .await
-        .map_err(|e| {
-            error::user(
-                &format!("Failed to write to {}: {}", last_run_dir.display(), e),
-                &format!(
-                    "Make sure you have permissions to write to {}",
-                    last_run_dir.display()
-                ),
-            )
-        })?;
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
 
     let mut pipeline_pb = ProgressBar::new_spinner().with_message("Starting pipeline...");
     pipeline_pb.enable_steady_tick(std::time::Duration::from_millis(100));
@@ -295,47 +834,49 @@ pub async fn run_submission_tests(
     pipeline_pb.set_message("Setting up virtual environment...");
 
     let env = global.init_venv(&pipeline_pb).await?;
+    load_python_env(&global.project, &args.env)?;
+
+    let inputs = args
+        .inputs
+        .as_deref()
+        .map(|path| Inputs::read(path)?.select(&tests))
+        .transpose()
+        .inspect_err(|_| pipeline_pb.finish_with_message("Failed to run pipeline"))?;
+    reset_last_run_dir(
+        &last_run_dir,
+        Some(&last_run_result_file),
+        tests.is_empty() || inputs.is_some(),
+    )
+    .await?;
 
     pipeline_pb.set_message("Converting notebooks...");
 
-    let modified_use_case = {
-        let mut use_case = use_case.clone();
-        let mut submission = submission.clone();
-        convert_submission_notebooks(&env, &mut submission).await?;
-        if let Err(err) = use_case.replace_refs(&submission.refs) {
-            return Err(error::system(
-                &format!("Failed to import pipeline: {err}"),
-                "Check the pipeline configuration and try again",
-            ));
-        }
-        use_case
-    };
+    let mut modified_use_case = submission_use_case(
+        &env,
+        &use_case,
+        submission,
+        args.convert_options(&global, &project),
+    )
+    .await?;
+    args.select_layers(&mut modified_use_case)?;
     let config = PipelineConfig {
-        data: dunce::canonicalize(data_path)?,
+        data: dunce::canonicalize(project_data_dir(&global.project))?,
     };
 
     wrap_python_output(&pipeline_pb)?;
 
-    let tests = tests
-        .iter()
-        .map(|test| {
-            test.parse::<usize>().map_err(|_| {
-                error::user(
-                    &format!("Invalid test index: {test}"),
-                    "Please provide a valid test index",
-                )
-            })
-        })
-        .collect::<Result<Vec<_>>>()?;
-
+    let layers = layer_names(&modified_use_case);
     let (num_inputs, aggregated) = run_pipeline(
         &env,
         RunPipelineConfig {
             use_case: modified_use_case,
             pipeline_config: config,
             tests: tests.clone(),
-            last_run_dir,
+            last_run_dir: last_run_dir.clone(),
             max_concurrency: global.max_concurrency,
+            aggregate: args.aggregate,
+            inputs,
+            watchdog: args.watchdog(),
         },
         None,
         &pipeline_pb,
@@ -351,6 +892,9 @@ pub async fn run_submission_tests(
                 ));
             }
             pipeline_pb.finish_and_clear();
+            if args.github_output {
+                write_submission_github_output(&score)?;
+            }
             Ok(score)
         }
         Ok(None) => {
@@ -379,6 +923,10 @@ pub async fn run_submission_tests(
         }
     };
 
+    let partial = !tests.is_empty()
+        || args.inputs.is_some()
+        || args.changes_layers()
+        || args.aggregate.is_some();
     let mut file = std::fs::File::create(&last_run_result_file).map_err(|e| {
         error::user(
             &format!(
@@ -396,15 +944,16 @@ pub async fn run_submission_tests(
         &mut file,
         &LastRunResult {
             info: EvaluateAllInfo {
-                score: if tests.is_empty() {
-                    result.as_ref().ok().cloned()
-                } else {
+                score: if partial {
                     None
+                } else {
+                    result.as_ref().ok().cloned()
                 },
                 num_inputs,
             },
             time: chrono::Utc::now(),
             use_case_version: use_case_toml.version(),
+            partial,
         },
     ) {
         return Err(error::user(
@@ -419,49 +968,113 @@ pub async fn run_submission_tests(
             ),
         ));
     }
+    if let Some(metrics_out) = args.metrics_out.as_ref() {
+        let tests = if args.inputs.is_some() {
+            Vec::new()
+        } else {
+            tests
+        };
+        write_metrics(
+            metrics_out,
+            &read_metrics(&last_run_dir, &layers, &tests, None).await?,
+        )?;
+    }
     result.map(|_| ())
 }
 
+async fn replay_submission(
+    global: &GlobalArgs,
+    project: &PyProject,
+    index: usize,
+    env_vars: &[(String, String)],
+    options: ConvertOptions,
+) -> Result<()> {
+    let submission = project
+        .aqora()
+        .and_then(|aqora| aqora.as_submission())
+        .ok_or_else(|| error::user("Submission config is not valid", ""))?;
+
+    let (_, use_case) = read_installed_use_case(global).await?;
+
+    let pb = ProgressBar::new_spinner().with_message("Setting up virtual environment...");
+    pb.enable_steady_tick(std::time::Duration::from_millis(100));
+    let env = global.init_venv(&pb).await?;
+    load_python_env(&global.project, env_vars)?;
+
+    pb.set_message("Converting notebooks...");
+    let use_case = submission_use_case(&env, &use_case, submission, options).await?;
+    pb.finish_and_clear();
+
+    replay(
+        &env,
+        ReplayConfig {
+            use_case,
+            pipeline_config: PipelineConfig {
+                data: dunce::canonicalize(project_data_dir(&global.project))?,
+            },
+            last_run_dir: project_last_run_dir(&global.project),
+            index,
+        },
+    )
+}
+
 pub async fn test_submission(args: Test, global: GlobalArgs, project: PyProject) -> Result<()> {
+    if let Some(index) = args.replay {
+        if !args.test.is_empty() {
+            return Err(error::user(
+                "--replay cannot be combined with --test for submissions",
+                "Remove the --test arguments and try again",
+            ));
+        }
+        return replay_submission(
+            &global,
+            &project,
+            index,
+            &args.env,
+            args.convert_options(&global, &project),
+        )
+        .await;
+    }
     let m = MultiProgress::new();
-    run_submission_tests(&m, &global, &project, args.test).await
+    run_submission_tests(&m, &global, &project, &args).await
 }
 
 async fn test_use_case_test(
     m: &MultiProgress,
     env: &PyEnv,
-    max_concurrency: usize,
-    last_run_dir: &Path,
+    global: &GlobalArgs,
+    args: &Test,
     use_case: &AqoraUseCaseConfig,
+    inputs: Option<&Inputs>,
     name: &str,
     indexes: Vec<usize>,
-) -> Result<()> {
+) -> Result<UseCaseTestReport> {
     let pb = m.insert_from_back(
         1,
         ProgressBar::new_spinner().with_message(format!("Running test {name}...")),
     );
     pb.enable_steady_tick(std::time::Duration::from_millis(100));
 
-    let modified_use_case = use_case.for_test(name).map_err(|err| {
+    let mut modified_use_case = use_case.for_test(name).map_err(|err| {
         pb.finish_with_message("Failed to run pipeline for {name}");
         error::user(
             &format!("Failed to load test config: {err}"),
             "Check the pipeline configuration and try again",
         )
     })?;
+    args.select_layers(&mut modified_use_case)
+        .inspect_err(|_| pb.finish_with_message(format!("Failed to run pipeline for {name}")))?;
+    let inputs = inputs
+        .map(|inputs| inputs.select(&indexes))
+        .transpose()
+        .inspect_err(|_| pb.finish_with_message(format!("Failed to run pipeline for {name}")))?;
 
-    let last_run_dir = last_run_dir.join(name);
-    tokio::fs::create_dir_all(&last_run_dir)
+    // Clear the results of earlier runs unless they are replayed, so that
+    // only the inputs of this run are read back for --metrics-out
+    let last_run_dir = project_last_run_dir(&global.project).join(name);
+    reset_last_run_dir(&last_run_dir, None, indexes.is_empty() || inputs.is_some())
         .await
-        .map_err(|e| {
-            error::user(
-                &format!("Failed to write to {}: {}", last_run_dir.display(), e),
-                &format!(
-                    "Make sure you have permissions to write to {}",
-                    last_run_dir.display()
-                ),
-            )
-        })?;
+        .inspect_err(|_| pb.finish_with_message(format!("Failed to run pipeline for {name}")))?;
 
     let config = PipelineConfig {
         data: modified_use_case.data.clone(),
@@ -474,7 +1087,10 @@ async fn test_use_case_test(
             pipeline_config: config,
             tests: indexes.clone(),
             last_run_dir,
-            max_concurrency,
+            max_concurrency: global.max_concurrency,
+            aggregate: args.aggregate,
+            inputs,
+            watchdog: args.watchdog(),
         },
         Some(name),
         &pb,
@@ -508,6 +1124,8 @@ async fn test_use_case_test(
         }
     };
 
+    let score_json = score_to_json(&result, name, &pb)?;
+
     if !indexes.is_empty() {
         if let Some(expected) = use_case
             .tests
@@ -524,49 +1142,314 @@ async fn test_use_case_test(
                     )
                 })?;
 
-            let score_json: serde_json::Value = Python::with_gil(|py| {
-                py.import("ujson")?
-                    .getattr("dumps")?
-                    .call1((result.clone(),))?
-                    .extract::<String>()
-            })
-            .map_err(|e| {
-                pb.suspend(|| {
-                    Python::with_gil(|py| e.print_and_set_sys_last_vars(py));
+            if expected_json != score_json {
+                pb.finish_with_message(format!("{name} score does not match the expected score"));
+                return Ok(UseCaseTestReport {
+                    name: name.to_string(),
+                    passed: false,
+                    score: Some(score_json),
+                    expected: Some(expected_json),
+                    error: None,
                 });
+            }
+        }
+    }
+
+    pb.finish_with_message(format!("Test {name} passed: {result}"));
+
+    Ok(UseCaseTestReport::passed(name, score_json))
+}
+
+/// Converts a score to the JSON it is reported as and compared against the
+/// expected score with
+fn score_to_json(score: &PyObject, name: &str, pb: &ProgressBar) -> Result<serde_json::Value> {
+    Python::with_gil(|py| dumps_score(py.import("ujson")?.getattr("dumps")?, score))
+        .map_err(|e| {
+            pb.suspend(|| {
+                Python::with_gil(|py| e.print_and_set_sys_last_vars(py));
+            });
+            pb.finish_with_message(format!("Failed to evaluate {name} score"));
+            error::user(
+                &format!("Failed to convert {name} score to JSON"),
+                "Check the pipeline configuration and try again",
+            )
+        })
+        .and_then(|s| {
+            serde_json::from_str(&s).map_err(|e| {
                 pb.finish_with_message(format!("Failed to evaluate {name} score"));
                 error::user(
-                    &format!("Failed to convert {name} score to JSON"),
+                    &format!("Failed to convert {name} score to JSON: {e}"),
                     "Check the pipeline configuration and try again",
                 )
             })
-            .and_then(|s| {
-                serde_json::from_str(&s).map_err(|e| {
-                    pb.finish_with_message(format!("Failed to evaluate {name} score"));
-                    error::user(
-                        &format!("Failed to convert {name} score to JSON: {e}"),
-                        "Check the pipeline configuration and try again",
+        })
+}
+
+/// Serializes a score with a Python `dumps` function such as `ujson.dumps`
+fn dumps_score(dumps: &PyAny, score: &PyObject) -> PyResult<String> {
+    dumps.call1((score.clone(),))?.extract()
+}
+
+#[derive(Debug, Serialize)]
+struct UseCaseTestReport {
+    name: String,
+    passed: bool,
+    score: Option<serde_json::Value>,
+    expected: Option<serde_json::Value>,
+    error: Option<String>,
+}
+
+impl UseCaseTestReport {
+    fn passed(name: &str, score: serde_json::Value) -> Self {
+        Self {
+            name: name.to_string(),
+            passed: true,
+            score: Some(score),
+            expected: None,
+            error: None,
+        }
+    }
+
+    fn failed(name: &str, error: String) -> Self {
+        Self {
+            name: name.to_string(),
+            passed: false,
+            score: None,
+            expected: None,
+            error: Some(error),
+        }
+    }
+
+    fn mismatch_error(&self) -> Option<error::Error> {
+        let (Some(expected), Some(score)) = (&self.expected, &self.score) else {
+            return None;
+        };
+        if self.passed {
+            return None;
+        }
+        Some(error::user(
+            &format!(
+                "Expected score for {name} does not match the actual score: {expected} != {score}",
+                name = self.name,
+            ),
+            "Check the pipeline configuration and try again",
+        ))
+    }
+
+    fn details(&self) -> String {
+        match (&self.error, &self.expected) {
+            (Some(error), _) => error.clone(),
+            (None, Some(expected)) if !self.passed => format!("expected {expected}"),
+            _ => String::new(),
+        }
+    }
+}
+
+fn github_output_error(err: std::io::Error) -> error::Error {
+    error::system(
+        &format!("Failed to write GitHub Actions output: {err}"),
+        "Check that GITHUB_OUTPUT and GITHUB_STEP_SUMMARY point to writable files",
+    )
+}
+
+fn write_submission_github_output(score: &PyObject) -> Result<()> {
+    let score = score.to_string();
+    github::write_outputs([("score", score.clone())]).map_err(github_output_error)?;
+    github::write_step_summary(&format!(
+        "### aqora test\n\n| Score |\n| --- |\n| {} |\n",
+        github::table_cell(&score)
+    ))
+    .map_err(github_output_error)
+}
+
+fn write_use_case_github_output(reports: &[UseCaseTestReport]) -> Result<()> {
+    let passed = reports.iter().all(|report| report.passed);
+    let scores = serde_json::to_string(
+        &reports
+            .iter()
+            .map(|report| (report.name.as_str(), report.score.as_ref()))
+            .collect::<std::collections::BTreeMap<_, _>>(),
+    )?;
+    github::write_outputs([("passed", passed.to_string()), ("scores", scores)])
+        .map_err(github_output_error)?;
+    let mut summary =
+        "### aqora test\n\n| Test | Status | Score |\n| --- | --- | --- |\n".to_string();
+    for report in reports {
+        let score = match report.score.as_ref() {
+            Some(serde_json::Value::String(score)) => score.clone(),
+            Some(score) => score.to_string(),
+            None => "-".to_string(),
+        };
+        summary.push_str(&format!(
+            "| {} | {} | {} |\n",
+            github::table_cell(&report.name),
+            if report.passed {
+                "✅ passed"
+            } else {
+                "❌ failed"
+            },
+            github::table_cell(&score)
+        ));
+    }
+    github::write_step_summary(&summary).map_err(github_output_error)
+}
+
+fn print_use_case_test_reports(reports: &[UseCaseTestReport], output: OutputFormat) {
+    match output {
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::json!({
+                "passed": reports.iter().all(|report| report.passed),
+                "tests": reports,
+            })
+        ),
+        OutputFormat::Text => {
+            let width = reports
+                .iter()
+                .map(|report| report.name.len())
+                .max()
+                .unwrap_or_default()
+                .max("TEST".len());
+            println!("{:width$}  STATUS  SCORE", "TEST");
+            for report in reports {
+                let status = if report.passed {
+                    format!(
+                        "{}",
+                        "pass".if_supports_color(OwoStream::Stdout, |text| text.green())
                     )
-                })
-            })?;
+                } else {
+                    format!(
+                        "{}",
+                        "FAIL".if_supports_color(OwoStream::Stdout, |text| text.red())
+                    )
+                };
+                let score = report
+                    .score
+                    .as_ref()
+                    .map(|score| match score {
+                        serde_json::Value::String(score) => score.clone(),
+                        score => score.to_string(),
+                    })
+                    .unwrap_or_else(|| "-".to_string());
+                println!(
+                    "{:width$}  {status}    {score}  {}",
+                    report.name,
+                    report.details()
+                );
+            }
+        }
+    }
+}
 
-            if expected_json != score_json {
-                pb.finish_with_message(format!("{name} score does not match the expected score"));
-                return Err(error::user(
-                &format!(
-                    "Expected score for {name} does not match the actual score: {expected} != {result}",
-                    expected = expected_json,
-                    result = score_json
-                ),
-                "Check the pipeline configuration and try again",
-            ));
+/// Resolves `name`, `name::index` and `name::*` selectors against the use case
+/// tests. The name may be a glob pattern such as `smoke_*`.
+fn select_use_case_tests(
+    use_case: &AqoraUseCaseConfig,
+    selectors: &[String],
+) -> Result<HashMap<String, Option<Vec<usize>>>> {
+    let mut acc = HashMap::<String, Option<Vec<usize>>>::new();
+    for selector in selectors {
+        let (pattern, index) = match selector.rsplit_once("::") {
+            Some((pattern, "*")) => (pattern, None),
+            Some((pattern, index)) => (
+                pattern,
+                Some(index.parse::<usize>().map_err(|_| {
+                    error::user(
+                        &format!("Invalid test index for {pattern}: {index}"),
+                        "Please provide a valid test index",
+                    )
+                })?),
+            ),
+            None => (selector.as_str(), None),
+        };
+        let matcher = globset::Glob::new(pattern)
+            .map_err(|err| {
+                error::user(
+                    &format!("Invalid test pattern {pattern}: {err}"),
+                    "Please provide a valid test name or glob pattern",
+                )
+            })?
+            .compile_matcher();
+        let mut matched = false;
+        for name in use_case
+            .tests
+            .keys()
+            .filter(|name| matcher.is_match(name.as_str()))
+        {
+            matched = true;
+            if let Some(maybe_indexes) = acc.get_mut(name) {
+                if let Some(indexes) = maybe_indexes {
+                    if let Some(index) = index {
+                        if !indexes.contains(&index) {
+                            indexes.push(index)
+                        }
+                    } else {
+                        *maybe_indexes = None;
+                    }
+                }
+            } else {
+                acc.insert(name.to_string(), index.map(|i| vec![i]));
             }
         }
+        if !matched {
+            return Err(error::user(
+                &format!("No tests found matching {pattern}"),
+                "Please provide a valid test name or glob pattern",
+            ));
+        }
     }
+    Ok(acc)
+}
 
-    pb.finish_with_message(format!("Test {name} passed: {result}"));
+async fn replay_use_case(
+    global: &GlobalArgs,
+    use_case: &AqoraUseCaseConfig,
+    tests: &[String],
+    index: usize,
+    env_vars: &[(String, String)],
+    options: ConvertOptions,
+) -> Result<()> {
+    let [name] = tests else {
+        return Err(error::user(
+            "--replay requires exactly one --test for use cases",
+            "Select the test to replay with `--test <name>`",
+        ));
+    };
+    if !use_case.tests.contains_key(name) {
+        return Err(error::user(
+            &format!("Test {name} not found"),
+            "Please provide a valid test name",
+        ));
+    }
 
-    Ok(())
+    let pb = ProgressBar::new_spinner().with_message("Setting up virtual environment...");
+    pb.enable_steady_tick(std::time::Duration::from_millis(100));
+    let env = global.init_venv(&pb).await?;
+    load_python_env(&global.project, env_vars)?;
+
+    pb.set_message("Converting notebooks...");
+    let mut use_case = use_case.clone();
+    convert_use_case_notebooks(&env, &mut use_case, options).await?;
+    pb.finish_and_clear();
+
+    let use_case = use_case.for_test(name).map_err(|err| {
+        error::user(
+            &format!("Failed to load test config: {err}"),
+            "Check the pipeline configuration and try again",
+        )
+    })?;
+
+    replay(
+        &env,
+        ReplayConfig {
+            pipeline_config: PipelineConfig {
+                data: use_case.data.clone(),
+            },
+            use_case,
+            last_run_dir: project_last_run_dir(&global.project).join(name),
+            index,
+        },
+    )
 }
 
 async fn test_use_case(args: Test, global: GlobalArgs, project: PyProject) -> Result<()> {
@@ -575,6 +1458,19 @@ async fn test_use_case(args: Test, global: GlobalArgs, project: PyProject) -> Re
         .aqora()
         .and_then(|aqora| aqora.as_use_case())
         .ok_or_else(|| error::user("Use case config is not valid", ""))?;
+    if let Some(index) = args.replay {
+        return replay_use_case(
+            &global,
+            use_case,
+            &args.test,
+            index,
+            &args.env,
+            args.convert_options(&global, &project),
+        )
+        .await;
+    }
+    // Fail before setting up the environment if a layer name is wrong
+    args.select_layers(&mut use_case.clone())?;
     let tests: HashMap<String, Option<Vec<usize>>> = if args.test.is_empty() {
         use_case
             .tests
@@ -582,41 +1478,7 @@ async fn test_use_case(args: Test, global: GlobalArgs, project: PyProject) -> Re
             .map(|name| (name.to_string(), None))
             .collect()
     } else {
-        args.test.iter().try_fold::<_, _, Result<_>>(
-            HashMap::<String, Option<Vec<usize>>>::new(),
-            |mut acc, test| {
-                let (name, index) = if let Some((name, index)) = test.rsplit_once("::") {
-                    (
-                        name,
-                        Some(index.parse::<usize>().map_err(|_| {
-                            error::user(
-                                &format!("Invalid test index for {name}: {index}"),
-                                "Please provide a valid test index",
-                            )
-                        })?),
-                    )
-                } else {
-                    (test.as_str(), None)
-                };
-                if let Some(maybe_indexes) = acc.get_mut(name) {
-                    if let Some(indexes) = maybe_indexes {
-                        if let Some(index) = index {
-                            indexes.push(index)
-                        } else {
-                            *maybe_indexes = None;
-                        }
-                    }
-                } else if use_case.tests.contains_key(name) {
-                    acc.insert(name.to_string(), index.map(|i| vec![i]));
-                } else {
-                    return Err(error::user(
-                        &format!("Test {name} not found"),
-                        "Please provide a valid test name",
-                    ));
-                }
-                Ok(acc)
-            },
-        )?
+        select_use_case_tests(use_case, &args.test)?
     };
 
     let venv_pb =
@@ -624,9 +1486,12 @@ async fn test_use_case(args: Test, global: GlobalArgs, project: PyProject) -> Re
     venv_pb.enable_steady_tick(std::time::Duration::from_millis(100));
 
     let env = global.init_venv(&venv_pb).await?;
+    load_python_env(&global.project, &args.env)?;
+    let inputs = args.inputs.as_deref().map(Inputs::read).transpose()?;
 
     let mut use_case = use_case.clone();
-    convert_use_case_notebooks(&env, &mut use_case).await?;
+    convert_use_case_notebooks(&env, &mut use_case, args.convert_options(&global, &project))
+        .await?;
 
     venv_pb.finish_with_message("Virtual environment ready");
 
@@ -635,29 +1500,101 @@ async fn test_use_case(args: Test, global: GlobalArgs, project: PyProject) -> Re
 
     wrap_python_output(&test_pb)?;
 
-    let last_run_dir = project_last_run_dir(&global.project);
+    let mut tests = tests.into_iter().collect::<Vec<_>>();
+    tests.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut reports = Vec::with_capacity(tests.len());
+    let mut metrics = Vec::new();
     for (name, indexes) in tests {
         let indexes = indexes.unwrap_or_default();
-        test_use_case_test(
+        let result = test_use_case_test(
             &m,
             &env,
-            global.max_concurrency,
-            &last_run_dir,
+            &global,
+            &args,
             &use_case,
+            inputs.as_ref(),
             &name,
-            indexes,
+            indexes.clone(),
         )
-        .await
-        .inspect_err(|_| test_pb.finish_with_message("Failed to run tests"))?;
+        .await;
+        if args.metrics_out.is_some() {
+            let indexes = if args.inputs.is_some() {
+                Vec::new()
+            } else {
+                indexes
+            };
+            let last_run_dir = project_last_run_dir(&global.project).join(&name);
+            if last_run_dir.exists() {
+                metrics.extend(
+                    read_metrics(
+                        &last_run_dir,
+                        &layer_names(&use_case),
+                        &indexes,
+                        Some(&name),
+                    )
+                    .await?,
+                );
+            }
+        }
+        if args.keep_going {
+            reports.push(
+                result.unwrap_or_else(|err| UseCaseTestReport::failed(&name, err.to_string())),
+            );
+            continue;
+        }
+        let report = result.inspect_err(|_| test_pb.finish_with_message("Failed to run tests"))?;
+        if let Some(err) = report.mismatch_error() {
+            test_pb.finish_with_message("Failed to run tests");
+            return Err(err);
+        }
+        reports.push(report);
+    }
+
+    let failed = reports.iter().filter(|report| !report.passed).count();
+    if failed > 0 {
+        test_pb.finish_with_message("Some tests failed");
+    } else {
+        test_pb.finish_with_message("All tests passed!");
+    }
+
+    if args.keep_going || matches!(args.output, OutputFormat::Json) {
+        m.suspend(|| print_use_case_test_reports(&reports, args.output));
+    }
+
+    if args.github_output {
+        write_use_case_github_output(&reports)?;
+    }
+
+    if let Some(metrics_out) = args.metrics_out.as_ref() {
+        write_metrics(metrics_out, &metrics)?;
     }
 
-    test_pb.finish_with_message("All tests passed!");
+    if failed > 0 {
+        return Err(error::user(
+            &format!("{failed} of {} test(s) failed", reports.len()),
+            "Check the summary above and try again",
+        ));
+    }
 
     Ok(())
 }
 
 pub async fn test(args: Test, global: GlobalArgs) -> Result<()> {
-    let project = read_pyproject(&global.project).await?;
+    if let Some(metrics_out) = args.metrics_out.as_ref() {
+        if !metrics_out
+            .extension()
+            .map_or(false, |ext| ext.eq_ignore_ascii_case("csv"))
+        {
+            return Err(error::user(
+                &format!(
+                    "Unsupported metrics output {}: only CSV is supported",
+                    metrics_out.display()
+                ),
+                "Pass a path ending in `.csv` to --metrics-out",
+            ));
+        }
+    }
+    let project = read_resolved_pyproject(&global.project).await?;
     let aqora = project.aqora().cloned().ok_or_else(|| {
         error::user(
             "No [tool.aqora] section found in pyproject.toml",
@@ -673,3 +1610,245 @@ pub async fn test(args: Test, global: GlobalArgs) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn extract_inputs(inputs: Vec<(usize, PyResult<PyObject>)>) -> Vec<(usize, String)> {
+        Python::with_gil(|py| {
+            inputs
+                .into_iter()
+                .map(|(index, input)| (index, input.unwrap().extract(py).unwrap()))
+                .collect()
+        })
+    }
+
+    #[test]
+    fn test_read_inputs_from_last_run_dir() {
+        pyo3::prepare_freethreaded_python();
+        let last_run_dir = tempfile::tempdir().unwrap();
+        for (index, input) in [(0, Some("a")), (1, None), (2, Some("c"))] {
+            let item = EvaluateInputInfo {
+                input: input.map(|input| Python::with_gil(|py| input.into_py(py))),
+                result: Default::default(),
+                error: None,
+            };
+            let mut file =
+                std::fs::File::create(last_run_dir.path().join(format!("{index}.msgpack")))
+                    .unwrap();
+            rmp_serde::encode::write(&mut file, &item).unwrap();
+        }
+        rmp_serde::encode::write(
+            &mut std::fs::File::create(last_run_dir.path().join("result.msgpack")).unwrap(),
+            &EvaluateAllInfo::default(),
+        )
+        .unwrap();
+
+        let inputs = Inputs::read(last_run_dir.path()).unwrap();
+        assert_eq!(
+            extract_inputs(inputs.select(&[]).unwrap()),
+            [(0, "a".to_string()), (2, "c".to_string())]
+        );
+        assert_eq!(
+            extract_inputs(inputs.select(&[3]).unwrap()),
+            [(2, "c".to_string())]
+        );
+        assert!(inputs.select(&[2]).is_err());
+        assert!(inputs.select(&[1, 1]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_inputs_survive_clearing_last_run_dir() {
+        pyo3::prepare_freethreaded_python();
+        let project_dir = tempfile::tempdir().unwrap();
+        let last_run_dir = project_last_run_dir(project_dir.path());
+        std::fs::create_dir_all(&last_run_dir).unwrap();
+        let item = EvaluateInputInfo {
+            input: Some(Python::with_gil(|py| "a".into_py(py))),
+            result: Default::default(),
+            error: None,
+        };
+        rmp_serde::encode::write(
+            &mut std::fs::File::create(last_run_dir.join("0.msgpack")).unwrap(),
+            &item,
+        )
+        .unwrap();
+
+        let inputs = Inputs::read(&last_run_dir).unwrap();
+        reset_last_run_dir(
+            &last_run_dir,
+            Some(&project_last_run_result(project_dir.path())),
+            true,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(std::fs::read_dir(&last_run_dir).unwrap().count(), 0);
+        assert_eq!(
+            extract_inputs(inputs.select(&[]).unwrap()),
+            [(0, "a".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reset_last_run_dir() {
+        let last_run_dir = tempfile::tempdir().unwrap();
+        let result_file = last_run_dir.path().join("result.msgpack");
+        std::fs::write(last_run_dir.path().join("0.msgpack"), "").unwrap();
+        std::fs::write(&result_file, "").unwrap();
+
+        reset_last_run_dir(last_run_dir.path(), Some(&result_file), false)
+            .await
+            .unwrap();
+        assert!(last_run_dir.path().join("0.msgpack").exists());
+        assert!(!result_file.exists());
+
+        reset_last_run_dir(last_run_dir.path(), None, true)
+            .await
+            .unwrap();
+        assert!(last_run_dir.path().exists());
+        assert!(!last_run_dir.path().join("0.msgpack").exists());
+    }
+
+    #[test]
+    fn test_read_inputs_from_sequence_file() {
+        #[derive(Serialize)]
+        struct Item {
+            #[serde(with = "aqora_runner::python::serde_pickle")]
+            input: PyObject,
+        }
+
+        pyo3::prepare_freethreaded_python();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("inputs.msgpack");
+        let items = Python::with_gil(|py| {
+            ["a", "b"]
+                .into_iter()
+                .map(|input| Item {
+                    input: input.into_py(py),
+                })
+                .collect::<Vec<_>>()
+        });
+        rmp_serde::encode::write(&mut std::fs::File::create(&path).unwrap(), &items).unwrap();
+
+        let inputs = Inputs::read(&path).unwrap();
+        assert_eq!(
+            extract_inputs(inputs.select(&[]).unwrap()),
+            [(0, "a".to_string()), (1, "b".to_string())]
+        );
+        assert_eq!(
+            extract_inputs(inputs.select(&[2]).unwrap()),
+            [(1, "b".to_string())]
+        );
+    }
+
+    fn use_case() -> AqoraUseCaseConfig {
+        toml::from_str(
+            r#"
+            data = "data"
+            generator = "use_case.generator"
+            aggregator = "use_case.aggregator"
+
+            [tests.smoke_small]
+            [tests.smoke_large]
+            [tests.full]
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn select(selectors: &[&str]) -> Result<Vec<(String, Option<Vec<usize>>)>> {
+        let selectors = selectors.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let mut selected = select_use_case_tests(&use_case(), &selectors)?
+            .into_iter()
+            .collect::<Vec<_>>();
+        selected.sort();
+        Ok(selected)
+    }
+
+    #[test]
+    fn test_select_use_case_tests_names_and_globs() {
+        assert_eq!(
+            select(&["full::2", "smoke_*"]).unwrap(),
+            [
+                ("full".to_string(), Some(vec![2])),
+                ("smoke_large".to_string(), None),
+                ("smoke_small".to_string(), None),
+            ]
+        );
+        assert_eq!(
+            select(&["smoke_*::1", "full::*"]).unwrap(),
+            [
+                ("full".to_string(), None),
+                ("smoke_large".to_string(), Some(vec![1])),
+                ("smoke_small".to_string(), Some(vec![1])),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_select_use_case_tests_no_match() {
+        assert!(select(&["missing"]).is_err());
+        assert!(select(&["full", "other_*"]).is_err());
+        assert!(select(&["full::x"]).is_err());
+    }
+
+    #[test]
+    fn test_select_use_case_tests_duplicates() {
+        assert_eq!(
+            select(&["full::1", "full::3", "full::1"]).unwrap(),
+            [("full".to_string(), Some(vec![1, 3]))]
+        );
+        assert_eq!(
+            select(&["smoke_small::1", "smoke_*"]).unwrap(),
+            [
+                ("smoke_large".to_string(), None),
+                ("smoke_small".to_string(), None),
+            ]
+        );
+        assert_eq!(
+            select(&["full", "full::2"]).unwrap(),
+            [("full".to_string(), None)]
+        );
+    }
+
+    #[test]
+    fn test_passed_report_score_is_json() {
+        pyo3::prepare_freethreaded_python();
+        // the standard library's json.dumps stands in for ujson, which is only
+        // installed in the project's venv
+        let score_json: serde_json::Value = Python::with_gil(|py| {
+            let score: PyObject = py
+                .eval("{'accuracy': 0.5, 'label': 'a'}", None, None)
+                .unwrap()
+                .into();
+            let dumps = py.import("json").unwrap().getattr("dumps").unwrap();
+            serde_json::from_str(&dumps_score(dumps, &score).unwrap()).unwrap()
+        });
+        let passed = UseCaseTestReport::passed("test", score_json.clone());
+        let mismatch = UseCaseTestReport {
+            name: "test".to_string(),
+            passed: false,
+            score: Some(score_json),
+            expected: Some(serde_json::json!(1)),
+            error: None,
+        };
+
+        assert_eq!(
+            serde_json::to_value(&passed).unwrap(),
+            serde_json::json!({
+                "name": "test",
+                "passed": true,
+                "score": {"accuracy": 0.5, "label": "a"},
+                "expected": null,
+                "error": null,
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(&passed).unwrap()["score"],
+            serde_json::to_value(&mismatch).unwrap()["score"]
+        );
+    }
+}
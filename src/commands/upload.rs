@@ -3,13 +3,13 @@ use crate::{
     compress::{compress, DEFAULT_ARCH_EXTENSION, DEFAULT_ARCH_MIME_TYPE},
     dirs::{
         project_last_run_dir, project_last_run_result, project_use_case_toml_path, pyproject_path,
-        read_pyproject,
+        read_pyproject, resolve_pyproject, PYPROJECT_FILENAME,
     },
     error::{self, Result},
-    graphql_client::{custom_scalars::*, GraphQLClient},
+    graphql_client::{custom_scalars::*, GraphQLClient, GraphQLError, Jitter, RetryPolicy},
     id::Id,
     ipynb::convert_project_notebooks,
-    progress_bar::default_spinner,
+    progress_bar::{default_spinner, pretty_bytes},
     python::{build_package, LastRunResult},
     readme::read_readme,
     revert_file::RevertFile,
@@ -21,8 +21,10 @@ use futures::prelude::*;
 use graphql_client::GraphQLQuery;
 use indicatif::{MultiProgress, ProgressBar};
 use serde::Serialize;
-use std::path::Path;
-use tempfile::tempdir;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
 use tracing::Instrument as _;
 use url::Url;
 
@@ -38,6 +40,72 @@ By default all competitions must comply to the rules defined in [aqora's Terms o
 #[command(author, version, about)]
 pub struct Upload {
     pub competition: Option<String>,
+    #[arg(
+        long,
+        help = "Submit to a target configured under [tool.aqora.targets] (submissions only)"
+    )]
+    pub target: Option<String>,
+    #[arg(
+        long,
+        help = "Keep the temporary build directory and the generated pyproject.toml for inspection"
+    )]
+    pub keep_build_config: bool,
+    #[arg(
+        long,
+        value_name = "FILE",
+        num_args = 0..=1,
+        default_missing_value = "-",
+        help = "Print the generated pyproject.toml that is built and submitted, or write it to FILE"
+    )]
+    pub print_generated_config: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Fail instead of prompting when the last test run is missing or out of date"
+    )]
+    pub require_fresh_tests: bool,
+    #[arg(
+        long,
+        help = "Join the competition and accept its rules without prompting"
+    )]
+    pub accept_rules: bool,
+    #[arg(
+        long,
+        default_value_t = RetryPolicy::default().retries,
+        help = "Number of times to retry an upload request that failed with a network or server error (each part of a large file is retried on its own)"
+    )]
+    pub retries: u32,
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = RetryPolicy::default().base_delay.as_millis() as u64,
+        help = "Delay in milliseconds before the first retry, doubled on each further attempt"
+    )]
+    pub retry_base_delay: u64,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "Stop retrying a failed upload request once this much time has passed since its first attempt"
+    )]
+    pub retry_max_elapsed: Option<u64>,
+    #[arg(
+        value_enum,
+        long,
+        default_value_t = RetryPolicy::default().jitter,
+        help = "Randomize the delay between retries"
+    )]
+    pub retry_jitter: Jitter,
+}
+
+impl Upload {
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            retries: self.retries,
+            base_delay: std::time::Duration::from_millis(self.retry_base_delay),
+            max_elapsed: self.retry_max_elapsed.map(std::time::Duration::from_secs),
+            jitter: self.retry_jitter,
+            ..Default::default()
+        }
+    }
 }
 
 #[derive(GraphQLQuery)]
@@ -68,12 +136,7 @@ async fn get_competition_by_slug(
                 "Please make sure the competition is correct",
             )
         })?;
-    let id = Id::parse_node_id(competition.id).map_err(|err| {
-        error::system(
-            &format!("Could not parse competition ID: {}", err),
-            "This is a bug, please report it",
-        )
-    })?;
+    let id = Id::parse_server_id(competition.id, "competition")?;
     let version = competition
         .use_case
         .latest
@@ -123,12 +186,7 @@ pub async fn get_submission_upload_info(
             "Please make sure the competition is correct",
         )
     })?;
-    let competition_id = Id::parse_node_id(competition.id).map_err(|err| {
-        error::system(
-            &format!("Could not parse competition ID: {}", err),
-            "This is a bug, please report it",
-        )
-    })?;
+    let competition_id = Id::parse_server_id(competition.id, "competition")?;
     let use_case_version = competition
         .use_case
         .latest
@@ -159,12 +217,7 @@ pub async fn get_submission_upload_info(
     } else {
         response.viewer.id
     };
-    let entity_id = Id::parse_node_id(entity_id).map_err(|err| {
-        error::system(
-            &format!("Could not parse entity ID: {}", err),
-            "This is a bug, please report it",
-        )
-    })?;
+    let entity_id = Id::parse_server_id(entity_id, "entity")?;
     Ok(SubmissionUploadInfoResponse {
         competition_id,
         use_case_version,
@@ -330,18 +383,75 @@ fn find_project_version_file<F: ProjectVersionFile>(
             "This is a bug, please report it",
         )
     })?;
-    let id = Id::parse_node_id(file.id()).map_err(|err| {
-        error::system(
-            &format!("Could not parse project version file ID: {}", err),
-            "This is a bug, please report it",
-        )
-    })?;
+    let id = Id::parse_server_id(file.id(), "project version file")?;
     let url = file
         .upload_url()
         .ok_or_else(|| error::system("No upload URL found", "This is a bug, please report it"))?;
     Ok((id, url))
 }
 
+/// A compressed or built file of a project version. Files are only uploaded
+/// once all of them are ready so that the total size is known up front.
+struct PendingUpload<'a> {
+    name: &'static str,
+    path: PathBuf,
+    id: Id,
+    upload_url: &'a Url,
+    pb: ProgressBar,
+}
+
+async fn upload_project_version_files(
+    client: &GraphQLClient,
+    uploads: Vec<PendingUpload<'_>>,
+    total_pb: &ProgressBar,
+) -> Result<()> {
+    let mut total_len = 0;
+    for upload in &uploads {
+        total_len += tokio::fs::metadata(&upload.path).await?.len();
+    }
+    total_pb.set_length(total_len);
+    futures::future::try_join_all(uploads.into_iter().map(|upload| {
+        let span = tracing::debug_span!("upload", file = upload.name);
+        async move {
+            upload
+                .pb
+                .set_message(format!("Uploading {}", upload.name.to_lowercase()));
+            let res = upload_project_version_file(
+                client,
+                &upload.path,
+                &upload.id,
+                Some(DEFAULT_ARCH_MIME_TYPE),
+                Some(upload.upload_url),
+                &upload.pb,
+                total_pb,
+            )
+            .await;
+            if res.is_ok() {
+                upload
+                    .pb
+                    .finish_with_message(format!("{} uploaded", upload.name));
+            } else {
+                upload.pb.finish_with_message(format!(
+                    "An error occurred while processing {}",
+                    upload.name.to_lowercase()
+                ));
+            }
+            res
+        }
+        .instrument(span)
+    }))
+    .instrument(tracing::debug_span!("try_join_all"))
+    .inspect(|res| {
+        if res.is_ok() {
+            total_pb.finish_with_message("All files uploaded");
+        } else {
+            total_pb.abandon_with_message("Upload failed");
+        }
+    })
+    .await?;
+    Ok(())
+}
+
 fn increment_version(version: &Version) -> Version {
     let mut release = version.release().to_vec();
     if let Some(patch) = release.last_mut() {
@@ -380,6 +490,27 @@ async fn update_pyproject_version(path: impl AsRef<Path>, version: &Version) ->
     Ok(())
 }
 
+/// Renders `project` over the pyproject.toml at `path`, keeping the comments
+/// and formatting of everything that did not change
+fn updated_pyproject_toml(path: impl AsRef<Path>, project: &PyProject) -> Result<String> {
+    let path = path.as_ref();
+    let mut document = std::fs::read_to_string(path)
+        .map_err(|err| {
+            error::user(
+                &format!("Could not read {}: {}", path.display(), err),
+                "Please make sure the file exists",
+            )
+        })?
+        .parse::<toml_edit::DocumentMut>()?;
+    project.apply_to_document(&mut document).map_err(|err| {
+        error::system(
+            &format!("Could not update {}: {}", path.display(), err),
+            "This is a bug, please report it",
+        )
+    })?;
+    Ok(document.to_string())
+}
+
 async fn update_project_version(
     project: &mut PyProject,
     project_path: impl AsRef<Path>,
@@ -418,12 +549,62 @@ Do you want to update the version to {new_version} now?"#
     Ok(version)
 }
 
+fn write_build_config(dist: &Path, pyproject_toml: &str, pb: &ProgressBar) -> Result<()> {
+    std::fs::create_dir_all(dist)?;
+    let path = dist.join(PYPROJECT_FILENAME);
+    std::fs::write(&path, pyproject_toml)?;
+    pb.println(format!(
+        "Generated build config written to {}",
+        path.display()
+    ));
+    Ok(())
+}
+
+fn notebook_paths(project: &PyProject) -> HashSet<String> {
+    project
+        .aqora()
+        .map(|aqora| {
+            aqora
+                .function_defs()
+                .into_iter()
+                .filter(|def| def.notebook)
+                .map(|def| def.path.to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Prints the generated pyproject.toml, or writes it to `target` unless it is `-`
+fn output_generated_config(target: &Path, pyproject_toml: &str, pb: &ProgressBar) -> Result<()> {
+    if target == Path::new("-") {
+        pb.suspend(|| print!("{pyproject_toml}"));
+        return Ok(());
+    }
+    std::fs::write(target, pyproject_toml).map_err(|err| {
+        error::user(
+            &format!("Could not write {}: {}", target.display(), err),
+            "Please make sure you have permission to write to the file",
+        )
+    })?;
+    pb.println(format!(
+        "Generated pyproject.toml written to {}",
+        target.display()
+    ));
+    Ok(())
+}
+
 #[tracing::instrument(skip(args, global, project), err)]
 pub async fn upload_use_case(
     args: Upload,
     global: GlobalArgs,
     mut project: PyProject,
 ) -> Result<()> {
+    if args.target.is_some() {
+        return Err(error::user(
+            "--target is only supported for submissions",
+            "Remove --target and try again",
+        ));
+    }
     let m = MultiProgress::new();
     check_login(global.clone(), &m).await?;
 
@@ -443,13 +624,26 @@ pub async fn upload_use_case(
 
     venv_pb.finish_with_message("Virtual environment initialized");
 
-    let tempdir = tempdir().map_err(|err| {
-        error::user(
-            &format!("could not create temporary directory: {}", err),
-            "Please make sure you have permission to create temporary directories",
-        )
-    })?;
-    let config = project
+    let keep_build_config = args.keep_build_config;
+    let print_generated_config = args.print_generated_config.clone();
+    let tempdir = global.tempdir(Some(&project), keep_build_config)?;
+    // paths are resolved to find the data and template, but the pyproject.toml
+    // is uploaded as written
+    let mut resolved = project.clone();
+    resolve_pyproject(&global.project, &mut resolved)?;
+    // notebooks are converted in the uploaded config, which does not have
+    // the layers of included files
+    let own_notebooks = notebook_paths(&project);
+    if let Some(path) = notebook_paths(&resolved)
+        .into_iter()
+        .find(|path| !own_notebooks.contains(path))
+    {
+        return Err(error::user(
+            &format!("Notebook {path} is used by an included file and cannot be uploaded"),
+            "Move the layers using notebooks into pyproject.toml",
+        ));
+    }
+    let config = resolved
         .aqora()
         .and_then(|aqora| aqora.as_use_case())
         .ok_or_else(|| {
@@ -481,7 +675,9 @@ pub async fn upload_use_case(
     use_case_pb.enable_steady_tick(std::time::Duration::from_millis(100));
     use_case_pb = m.add(use_case_pb);
 
-    let client = GraphQLClient::new(global.url.parse()?).await?;
+    let client = GraphQLClient::new(global.url.parse()?)
+        .await?
+        .with_retry_policy(args.retry_policy());
     let competition = get_competition_by_slug(&client, slug).await?;
 
     let version = update_project_version(
@@ -532,6 +728,12 @@ pub async fn upload_use_case(
 
     use_case_pb.finish_with_message("Version updated");
 
+    let total_pb = m.add(
+        ProgressBar::new(0)
+            .with_style(pretty_bytes())
+            .with_message("Total uploaded"),
+    );
+
     let data_fut = {
         let (id, upload_url) = find_project_version_file(
             &project_version.files,
@@ -545,7 +747,6 @@ pub async fn upload_use_case(
         data_pb = m.add(data_pb);
 
         let data_pb_cloned = data_pb.clone();
-        let client = client.clone();
         async move {
             data_pb_cloned.set_message("Compressing data");
             compress(data_path, &data_tar_file, &data_pb_cloned, true)
@@ -556,24 +757,17 @@ pub async fn upload_use_case(
                         "Please make sure the data directory is valid",
                     )
                 })?;
-            data_pb_cloned.set_message("Uploading data");
-            upload_project_version_file(
-                &client,
-                data_tar_file,
-                &id,
-                Some(DEFAULT_ARCH_MIME_TYPE),
-                Some(upload_url),
-                &data_pb_cloned,
-            )
-            .await
+            Ok::<_, error::Error>(PendingUpload {
+                name: "Data",
+                path: data_tar_file,
+                id,
+                upload_url,
+                pb: data_pb_cloned,
+            })
         }
         .instrument(tracing::debug_span!("data"))
-        .inspect(move |res| {
-            if res.is_ok() {
-                data_pb.finish_with_message("Data uploaded");
-            } else {
-                data_pb.finish_with_message("An error occurred while processing data");
-            }
+        .inspect_err(move |_| {
+            data_pb.finish_with_message("An error occurred while processing data");
         })
         .boxed()
     };
@@ -592,7 +786,6 @@ pub async fn upload_use_case(
             template_pb = m.add(template_pb);
 
             let template_pb_cloned = template_pb.clone();
-            let client = client.clone();
             async move {
                 template_pb_cloned.set_message("Compressing template");
                 compress(
@@ -609,28 +802,21 @@ pub async fn upload_use_case(
                     )
                 })?;
 
-                template_pb_cloned.set_message("Uploading template");
-                upload_project_version_file(
-                    &client,
-                    template_tar_file,
-                    &id,
-                    Some(DEFAULT_ARCH_MIME_TYPE),
-                    Some(upload_url),
-                    &template_pb_cloned,
-                )
-                .await
+                Ok::<_, error::Error>(Some(PendingUpload {
+                    name: "Template",
+                    path: template_tar_file,
+                    id,
+                    upload_url,
+                    pb: template_pb_cloned,
+                }))
             }
             .instrument(tracing::debug_span!("template"))
-            .inspect(move |res| {
-                if res.is_ok() {
-                    template_pb.finish_with_message("Template uploaded");
-                } else {
-                    template_pb.finish_with_message("An error occurred while processing template");
-                }
+            .inspect_err(move |_| {
+                template_pb.finish_with_message("An error occurred while processing template");
             })
             .boxed()
         } else {
-            futures::future::ready(Ok(())).boxed()
+            futures::future::ready(Ok(None)).boxed()
         }
     };
 
@@ -640,54 +826,60 @@ pub async fn upload_use_case(
             update_use_case_mutation::ProjectVersionFileKind::PACKAGE,
         )?;
         let package_build_path = tempdir.path().join("dist");
+        let package_tmp_path = tempdir.path().to_path_buf();
         let package_tar_file = package_build_path.join(format!("{package_name}-{version}.tar.gz"));
         let mut package_pb = ProgressBar::new_spinner().with_message("Building package");
         package_pb.enable_steady_tick(std::time::Duration::from_millis(100));
         package_pb = m.add(package_pb);
 
         let package_pb_cloned = package_pb.clone();
-        let client = client.clone();
         async move {
             package_pb_cloned.set_message("Building package");
             let project_file = RevertFile::save(pyproject_path(&global.project))?;
             let mut new_project = project.clone();
             new_project.set_name(package_name);
-            convert_project_notebooks(&env, new_project.aqora_mut().unwrap()).await?;
-            std::fs::write(&project_file, new_project.toml()?)?;
+            convert_project_notebooks(&env, new_project.aqora_mut().unwrap(), Default::default())
+                .await?;
+            let new_project_toml = updated_pyproject_toml(&project_file, &new_project)?;
+            std::fs::write(&project_file, &new_project_toml)?;
+            if keep_build_config {
+                write_build_config(&package_build_path, &new_project_toml, &package_pb_cloned)?;
+            }
+            if let Some(target) = print_generated_config.as_ref() {
+                output_generated_config(target, &new_project_toml, &package_pb_cloned)?;
+            }
             build_package(
                 &env,
                 &global.project,
                 &package_build_path,
+                &package_tmp_path,
                 &package_pb_cloned,
             )
             .await?;
             project_file.revert()?;
 
-            package_pb_cloned.set_message("Uploading package");
-            upload_project_version_file(
-                &client,
-                package_tar_file,
-                &id,
-                Some(DEFAULT_ARCH_MIME_TYPE),
-                Some(upload_url),
-                &package_pb_cloned,
-            )
-            .await
+            Ok::<_, error::Error>(PendingUpload {
+                name: "Package",
+                path: package_tar_file,
+                id,
+                upload_url,
+                pb: package_pb_cloned,
+            })
         }
         .instrument(tracing::debug_span!("package"))
-        .inspect(move |res| {
-            if res.is_ok() {
-                package_pb.finish_with_message("Package uploaded");
-            } else {
-                package_pb.finish_with_message("An error occurred while processing package");
-            }
+        .inspect_err(move |_| {
+            package_pb.finish_with_message("An error occurred while processing package");
         })
         .boxed()
     };
 
-    futures::future::try_join_all([data_fut, template_fut, package_fut])
-        .instrument(tracing::debug_span!("try_join_all"))
-        .await?;
+    let (data, template, package) = futures::try_join!(data_fut, template_fut, package_fut)
+        .inspect_err(|_| total_pb.abandon_with_message("Upload failed"))?;
+    let uploads = [Some(data), template, Some(package)]
+        .into_iter()
+        .flatten()
+        .collect();
+    upload_project_version_files(&client, uploads, &total_pb).await?;
 
     let mut validate_pb = ProgressBar::new_spinner().with_message("Validating use case");
     validate_pb.enable_steady_tick(std::time::Duration::from_millis(100));
@@ -740,12 +932,9 @@ pub async fn upload_submission(
     use_case_pb.enable_steady_tick(std::time::Duration::from_millis(100));
     use_case_pb = m.add(use_case_pb);
 
-    let tempdir = tempdir().map_err(|err| {
-        error::user(
-            &format!("could not create temporary directory: {}", err),
-            "Please make sure you have permission to create temporary directories",
-        )
-    })?;
+    let keep_build_config = args.keep_build_config;
+    let print_generated_config = args.print_generated_config.clone();
+    let tempdir = global.tempdir(Some(&project), keep_build_config)?;
     let config = project
         .aqora()
         .and_then(|aqora| aqora.as_submission())
@@ -779,10 +968,16 @@ pub async fn upload_submission(
         )
     })?;
 
+    let target = config.target(args.target.as_deref()).map_err(|err| {
+        error::user(
+            &err.to_string(),
+            "Check the targets under [tool.aqora.targets] in your pyproject.toml",
+        )
+    })?;
     let slug = args
         .competition
         .as_ref()
-        .or(config.competition.as_ref())
+        .or(target.competition.as_ref())
         .ok_or_else(|| {
             error::user(
                 "No competition provided",
@@ -790,13 +985,15 @@ pub async fn upload_submission(
             )
         })?;
 
-    let client = GraphQLClient::new(global.url.parse()?).await?;
+    let client = GraphQLClient::new(global.url.parse()?)
+        .await?
+        .with_retry_policy(args.retry_policy());
 
     let SubmissionUploadInfoResponse {
         entity_id,
         competition_id,
         use_case_version,
-    } = get_submission_upload_info(&client, slug, config.entity.as_ref()).await?;
+    } = get_submission_upload_info(&client, slug, target.entity.as_ref()).await?;
 
     let LatestSubmissionVersionResponse {
         version: submission_version,
@@ -817,36 +1014,51 @@ pub async fn upload_submission(
             rules.push_str(&format!("\n\n{rule_text}"));
         }
 
-        let accepts = m.suspend(|| {
-            let will_review = global
-                .confirm()
-                .with_prompt(format!("{message} Would you like to review them now?"))
-                .default(true)
-                .interact()
-                .ok()
-                .unwrap_or_default();
-            if !will_review {
-                return false;
-            }
-            if dialoguer::Editor::new().edit(&rules).is_err() {
-                return false;
-            }
-            global
-                .confirm()
-                .with_prompt("Would you like to accept?")
-                .no_prompt_value(true)
-                .interact()
-                .ok()
-                .unwrap_or_default()
-        });
+        let mut rules_url = global.aqora_url()?;
+        rules_url.set_path(&format!("competitions/{slug}/rules"));
+        let accepts = if args.accept_rules {
+            m.println(format!("Accepting the competition rules at {rules_url}"))?;
+            true
+        } else if global.no_prompt {
+            // Rules are only accepted without being shown with --accept-rules
+            false
+        } else {
+            m.suspend(|| {
+                let will_review = global
+                    .confirm()
+                    .with_prompt(format!("{message} Would you like to review them now?"))
+                    .default(true)
+                    .interact()
+                    .ok()
+                    .unwrap_or_default();
+                if !will_review {
+                    return false;
+                }
+                if dialoguer::Editor::new().edit(&rules).is_err() {
+                    return false;
+                }
+                global
+                    .confirm()
+                    .with_prompt("Would you like to accept?")
+                    .interact()
+                    .ok()
+                    .unwrap_or_default()
+            })
+        };
         if !accepts {
-            let mut url = global.aqora_url().unwrap();
-            url.set_path(&format!("competitions/{slug}/rules"));
             return Err(error::user(
                 message,
-                &format!("Please agree to the competition rules at {url}",),
+                &format!(
+                    "Please agree to the competition rules at {rules_url} or pass --accept-rules"
+                ),
             ));
         }
+        let accept_error = |err: GraphQLError| {
+            error::user(
+                &format!("Could not accept the competition rules: {err}"),
+                &format!("Please agree to the competition rules at {rules_url}"),
+            )
+        };
         let pb = m.add(default_spinner().with_message("Accepting rules..."));
         if !is_member {
             client
@@ -854,14 +1066,16 @@ pub async fn upload_submission(
                     competition_id: competition_id.to_node_id(),
                     entity_id: entity_id.to_node_id(),
                 })
-                .await?;
+                .await
+                .map_err(accept_error)?;
         }
         client
             .send::<AcceptCompetitionRules>(accept_competition_rules::Variables {
                 competition_id: competition_id.to_node_id(),
                 entity_id: entity_id.to_node_id(),
             })
-            .await?;
+            .await
+            .map_err(accept_error)?;
         pb.finish_with_message("Rules accepted");
     }
 
@@ -874,6 +1088,12 @@ pub async fn upload_submission(
 
     let evaluation_path = project_last_run_dir(&global.project);
     if !evaluation_path.exists() {
+        if args.require_fresh_tests {
+            return Err(error::user(
+                "No last run result found",
+                "Please make sure you have run `aqora test`",
+            ));
+        }
         let confirmation = m.suspend(|| {
             global
                 .confirm()
@@ -885,7 +1105,7 @@ Would you like to run the tests now?"#,
                 .interact()
         })?;
         if confirmation {
-            run_submission_tests(&m, &global, &project, Default::default()).await?;
+            run_submission_tests(&m, &global, &project, &Default::default()).await?;
         } else {
             return Err(error::user(
                 "No last run result found",
@@ -906,7 +1126,39 @@ Would you like to run the tests now?"#,
             });
 
     if let Ok(last_run_result) = last_run_result.as_ref() {
-        if last_run_result.use_case_version.as_ref() != Some(&use_case_version) {
+        if last_run_result.partial {
+            if args.require_fresh_tests {
+                return Err(error::user(
+                    "The last test run only ran part of the pipeline or used another aggregator",
+                    "Please re-run `aqora test` without --test, --inputs, --skip-layer, --only-through or --aggregate",
+                ));
+            }
+            let confirmation = m.suspend(|| {
+                global
+                    .confirm()
+                    .with_prompt(
+                        r#"It seems the last test run only ran part of the pipeline or used another aggregator.
+It is required to run all the tests again.
+Do you want to run the tests now?"#,
+                    )
+                    .default(true)
+                    .interact()
+            })?;
+            if confirmation {
+                run_submission_tests(&m, &global, &project, &Default::default()).await?;
+            } else {
+                return Err(error::user(
+                    "The last test run only ran part of the pipeline or used another aggregator",
+                    "Please re-run `aqora test` without --test, --inputs, --skip-layer, --only-through or --aggregate",
+                ));
+            }
+        } else if last_run_result.use_case_version.as_ref() != Some(&use_case_version) {
+            if args.require_fresh_tests {
+                return Err(error::user(
+                    "Use case version does not match last run result",
+                    "Please re-run `aqora test`",
+                ));
+            }
             let confirmation = m.suspend(|| {
                 global
                     .confirm()
@@ -919,7 +1171,7 @@ Do you want to run the tests now?"#,
                     .interact()
             })?;
             if confirmation {
-                run_submission_tests(&m, &global, &project, Default::default()).await?;
+                run_submission_tests(&m, &global, &project, &Default::default()).await?;
             } else {
                 return Err(error::user(
                     "Use case version does not match last run result",
@@ -944,6 +1196,12 @@ Do you want to run the tests now?"#,
                     }
                 }
             }
+            if should_run_tests && args.require_fresh_tests {
+                return Err(error::user(
+                    "Files have been modified since the last test run",
+                    "Please re-run `aqora test`",
+                ));
+            }
             if should_run_tests {
                 let confirmation = m.suspend(|| {
                     global
@@ -957,11 +1215,17 @@ Do you want to re-run the tests now?"#,
                         .interact()
                 })?;
                 if confirmation {
-                    run_submission_tests(&m, &global, &project, Default::default()).await?;
+                    run_submission_tests(&m, &global, &project, &Default::default()).await?;
                 }
             }
         }
     } else {
+        if args.require_fresh_tests {
+            return Err(error::user(
+                "Last test run result is corrupted or missing",
+                "Please re-run `aqora test`",
+            ));
+        }
         let confirmation = m.suspend(|| {
             global
                 .confirm()
@@ -974,7 +1238,7 @@ Do you want to run the tests now?"#,
                 .interact()
         })?;
         if confirmation {
-            run_submission_tests(&m, &global, &project, Default::default()).await?;
+            run_submission_tests(&m, &global, &project, &Default::default()).await?;
         } else {
             return Err(error::user(
                 "Last test run result is corrupted or missing",
@@ -1014,6 +1278,12 @@ Do you want to run the tests now?"#,
         entity_id.to_package_id()
     );
 
+    let total_pb = m.add(
+        ProgressBar::new(0)
+            .with_style(pretty_bytes())
+            .with_message("Total uploaded"),
+    );
+
     let evaluation_fut = {
         let (id, upload_url) = find_project_version_file(
             &project_version.files,
@@ -1027,7 +1297,6 @@ Do you want to run the tests now?"#,
         evaluation_pb = m.add(evaluation_pb);
 
         let evaluation_pb_cloned = evaluation_pb.clone();
-        let client = client.clone();
         async move {
             compress(
                 evaluation_path,
@@ -1042,24 +1311,17 @@ Do you want to run the tests now?"#,
                     "Please make sure the evaluation directory is valid",
                 )
             })?;
-            evaluation_pb_cloned.set_message("Uploading evaluation");
-            upload_project_version_file(
-                &client,
-                evaluation_tar_file,
-                &id,
-                Some(DEFAULT_ARCH_MIME_TYPE),
-                Some(upload_url),
-                &evaluation_pb_cloned,
-            )
-            .await
+            Ok::<_, error::Error>(PendingUpload {
+                name: "Evaluation",
+                path: evaluation_tar_file,
+                id,
+                upload_url,
+                pb: evaluation_pb_cloned,
+            })
         }
         .instrument(tracing::debug_span!("evaluation"))
-        .inspect(move |res| {
-            if res.is_ok() {
-                evaluation_pb.finish_with_message("Evaluation uploaded");
-            } else {
-                evaluation_pb.finish_with_message("An error occurred while processing evaluation");
-            }
+        .inspect_err(move |_| {
+            evaluation_pb.finish_with_message("An error occurred while processing evaluation");
         })
         .boxed()
     };
@@ -1070,51 +1332,55 @@ Do you want to run the tests now?"#,
             update_submission_mutation::ProjectVersionFileKind::PACKAGE,
         )?;
         let package_build_path = tempdir.path().join("dist");
+        let package_tmp_path = tempdir.path().to_path_buf();
         let package_tar_file = package_build_path.join(format!("{package_name}-{version}.tar.gz"));
         let mut package_pb = ProgressBar::new_spinner().with_message("Building package");
         package_pb.enable_steady_tick(std::time::Duration::from_millis(100));
         package_pb = m.add(package_pb);
 
         let package_pb_cloned = package_pb.clone();
-        let client = client.clone();
         async move {
             let project_file = RevertFile::save(pyproject_path(&global.project))?;
             let mut new_project = project.clone();
             new_project.set_name(package_name);
-            convert_project_notebooks(&env, new_project.aqora_mut().unwrap()).await?;
-            std::fs::write(&project_file, new_project.toml()?)?;
+            convert_project_notebooks(&env, new_project.aqora_mut().unwrap(), Default::default())
+                .await?;
+            let new_project_toml = updated_pyproject_toml(&project_file, &new_project)?;
+            std::fs::write(&project_file, &new_project_toml)?;
+            if keep_build_config {
+                write_build_config(&package_build_path, &new_project_toml, &package_pb_cloned)?;
+            }
+            if let Some(target) = print_generated_config.as_ref() {
+                output_generated_config(target, &new_project_toml, &package_pb_cloned)?;
+            }
             build_package(
                 &env,
                 &global.project,
                 package_build_path,
+                package_tmp_path,
                 &package_pb_cloned,
             )
             .await?;
             project_file.revert()?;
 
-            package_pb_cloned.set_message("Uploading package");
-            upload_project_version_file(
-                &client,
-                package_tar_file,
-                &id,
-                Some(DEFAULT_ARCH_MIME_TYPE),
-                Some(upload_url),
-                &package_pb_cloned,
-            )
-            .await
+            Ok::<_, error::Error>(PendingUpload {
+                name: "Package",
+                path: package_tar_file,
+                id,
+                upload_url,
+                pb: package_pb_cloned,
+            })
         }
         .instrument(tracing::debug_span!("package"))
-        .inspect(move |res| {
-            if res.is_ok() {
-                package_pb.finish_with_message("Package uploaded");
-            } else {
-                package_pb.finish_with_message("An error occurred while processing package");
-            }
+        .inspect_err(move |_| {
+            package_pb.finish_with_message("An error occurred while processing package");
         })
         .boxed()
     };
 
-    futures::future::try_join_all([evaluation_fut, package_fut]).await?;
+    let (evaluation, package) = futures::try_join!(evaluation_fut, package_fut)
+        .inspect_err(|_| total_pb.abandon_with_message("Upload failed"))?;
+    upload_project_version_files(&client, vec![evaluation, package], &total_pb).await?;
 
     let mut validate_pb = ProgressBar::new_spinner().with_message("Validating submission");
     validate_pb.enable_steady_tick(std::time::Duration::from_millis(100));
@@ -1150,3 +1416,88 @@ pub async fn upload(args: Upload, global: GlobalArgs) -> Result<()> {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dirs::read_resolved_pyproject;
+
+    const USE_CASE_PYPROJECT: &str = r#"[project]
+name = "use-case"
+version = "0.1.0"
+
+[tool.aqora]
+type = "use_case"
+# resolved from the environment when running locally
+data = "${AQORA_TEST_UPLOAD_DATA_ROOT}/train"
+generator = "use_case.generator"
+aggregator = "use_case.aggregator"
+
+[[tool.aqora.layers]]
+name = "model"
+transform = "use_case.transform"
+"#;
+
+    #[tokio::test]
+    async fn test_updated_pyproject_toml_keeps_env_vars() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = pyproject_path(dir.path());
+        std::fs::write(&path, USE_CASE_PYPROJECT).unwrap();
+        std::env::set_var("AQORA_TEST_UPLOAD_DATA_ROOT", "/mnt/data");
+
+        let project = read_pyproject(dir.path()).await.unwrap();
+        assert_eq!(
+            updated_pyproject_toml(&path, &project).unwrap(),
+            USE_CASE_PYPROJECT
+        );
+
+        let resolved = read_resolved_pyproject(dir.path()).await.unwrap();
+        assert_eq!(
+            resolved.aqora().unwrap().as_use_case().unwrap().data,
+            PathBuf::from("/mnt/data/train")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_updated_pyproject_toml_keeps_includes() {
+        let pyproject = r#"[project]
+name = "use-case"
+version = "0.1.0"
+
+[tool.aqora]
+type = "use_case"
+data = "data"
+generator = "use_case.generator"
+aggregator = "use_case.aggregator"
+include = ["layers/*.toml"]
+
+[[tool.aqora.layers]]
+name = "model"
+transform = "use_case.transform"
+"#;
+        let dir = tempfile::tempdir().unwrap();
+        let path = pyproject_path(dir.path());
+        std::fs::write(&path, pyproject).unwrap();
+        std::fs::create_dir(dir.path().join("layers")).unwrap();
+        std::fs::write(
+            dir.path().join("layers").join("metric.toml"),
+            "[[layers]]\nname = \"metric\"\nmetric = \"use_case.metric\"\n",
+        )
+        .unwrap();
+
+        let project = read_pyproject(dir.path()).await.unwrap();
+        assert_eq!(updated_pyproject_toml(&path, &project).unwrap(), pyproject);
+
+        let resolved = read_resolved_pyproject(dir.path()).await.unwrap();
+        let use_case = resolved.aqora().unwrap().as_use_case().unwrap();
+        assert!(use_case.include.is_empty());
+        assert_eq!(
+            use_case
+                .layers
+                .iter()
+                .map(|layer| layer.name.as_str())
+                .collect::<Vec<_>>(),
+            ["model", "metric"]
+        );
+    }
+}
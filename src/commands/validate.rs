@@ -0,0 +1,112 @@
+use crate::{
+    commands::GlobalArgs,
+    dirs::read_resolved_pyproject,
+    error::{self, Result},
+};
+use aqora_config::{PathStr, PyProject};
+use clap::{Args, ValueEnum};
+use owo_colors::{OwoColorize, Stream as OwoStream};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Args, Debug, Serialize)]
+#[command(author, version, about)]
+pub struct Validate {
+    #[arg(value_enum, long, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+}
+
+/// Looks for `module/name.ipynb` from the project root and its `src`
+/// directory without needing the virtual environment
+fn notebook_exists(project_dir: &Path, path: &PathStr) -> bool {
+    let module = path.module().to_string();
+    let relative = module
+        .split('.')
+        .filter(|part| !part.is_empty())
+        .collect::<PathBuf>()
+        .join(Path::new(path.name()).with_extension("ipynb"));
+    [project_dir.to_path_buf(), project_dir.join("src")]
+        .iter()
+        .any(|root| root.join(&relative).exists())
+}
+
+pub(crate) fn validate_project(project_dir: &Path, project: &PyProject) -> Vec<String> {
+    let mut problems = project
+        .validate_all()
+        .into_iter()
+        .map(|err| err.to_string())
+        .collect::<Vec<_>>();
+    if let Some(use_case) = project.aqora().and_then(|aqora| aqora.as_use_case()) {
+        for (what, path) in [
+            ("data", Some(&use_case.data)),
+            ("template", use_case.template.as_ref()),
+        ] {
+            if let Some(path) = path {
+                if !project_dir.join(path).exists() {
+                    problems.push(format!(
+                        "Could not find {what} directory {}",
+                        path.display()
+                    ));
+                }
+            }
+        }
+    }
+    if let Some(aqora) = project.aqora() {
+        let mut missing = aqora
+            .function_defs()
+            .into_iter()
+            .filter(|def| def.notebook && !def.path.has_ref())
+            .filter(|def| !notebook_exists(project_dir, &def.path))
+            .map(|def| format!("Could not find notebook for {}", def.path))
+            .collect::<Vec<_>>();
+        missing.sort();
+        missing.dedup();
+        problems.extend(missing);
+    }
+    problems
+}
+
+pub async fn validate(args: Validate, global: GlobalArgs) -> Result<()> {
+    let problems = match read_resolved_pyproject(&global.project).await {
+        Ok(project) => validate_project(&global.project, &project),
+        Err(err) => vec![err.to_string()],
+    };
+
+    match args.output {
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::json!({
+                "valid": problems.is_empty(),
+                "problems": problems,
+            })
+        ),
+        OutputFormat::Text => {
+            for problem in problems.iter() {
+                println!(
+                    "{} {problem}",
+                    "error:".if_supports_color(OwoStream::Stdout, |text| text.red())
+                );
+            }
+            if problems.is_empty() {
+                println!("Project configuration is valid");
+            }
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(error::user(
+            &format!("Found {} problem(s) in the project", problems.len()),
+            "Fix the problems above and try again",
+        ))
+    }
+}
@@ -0,0 +1,40 @@
+use crate::{
+    commands::{info::get_viewer_info, validate::OutputFormat, GlobalArgs},
+    credentials::get_credentials,
+    error::{self, Result},
+};
+use clap::Args;
+use serde::Serialize;
+
+#[derive(Args, Debug, Serialize)]
+#[command(author, version, about)]
+pub struct Whoami {
+    #[arg(value_enum, long, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+}
+
+pub async fn whoami(args: Whoami, global: GlobalArgs) -> Result<()> {
+    let url = global.aqora_url()?;
+    if get_credentials(url.clone()).await?.is_none() {
+        return Err(error::auth(
+            "Not logged in",
+            "Run `aqora login` to connect your account",
+        ));
+    }
+    let viewer = get_viewer_info(&global).await?;
+    match args.output {
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::json!({
+                "username": viewer.username,
+                "display_name": viewer.display_name,
+                "url": url,
+            })
+        ),
+        OutputFormat::Text => {
+            println!("{} ({})", viewer.username, viewer.display_name);
+            println!("Logged in to {url}");
+        }
+    }
+    Ok(())
+}
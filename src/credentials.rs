@@ -51,9 +51,9 @@ impl Credentials {
         .oauth2_refresh
         .issued
         .ok_or_else(|| {
-            error::system(
-                "GraphQL response missing issued",
-                "This is a bug, please report it",
+            error::auth(
+                "Your aqora session has expired",
+                "Run `aqora login` to connect your account again",
             )
         })?;
 
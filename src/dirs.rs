@@ -5,8 +5,9 @@ use crate::{
     error::{self, Result},
     manifest::manifest_name,
     process::run_command,
+    revert_file::CleanupTempDir,
 };
-use aqora_config::PyProject;
+use aqora_config::{AqoraConfig, PyProject};
 use aqora_runner::python::{ColorChoice, LinkMode, PyEnv, PyEnvOptions, BIN_PATH};
 use indicatif::ProgressBar;
 use serde::{Deserialize, Serialize};
@@ -20,10 +21,11 @@ const DATA_DIRNAME: &str = "data";
 const VENV_DIRNAME: &str = ".venv";
 const VSCODE_DIRNAME: &str = ".vscode";
 const LAST_RUN_DIRNAME: &str = "last_run";
-const PYPROJECT_FILENAME: &str = "pyproject.toml";
+pub const PYPROJECT_FILENAME: &str = "pyproject.toml";
 const USE_CASE_FILENAME: &str = "use_case.toml";
 const PROJECT_CONFIG_FILENAME: &str = "config.toml";
 const VSCODE_SETTINGS_FILENAME: &str = "settings.json";
+const MIN_TMP_DIR_FREE_SPACE: u64 = 512 * 1024 * 1024;
 
 pub async fn config_dir() -> Result<PathBuf> {
     let mut path = dirs::data_dir().or_else(dirs::config_dir).ok_or_else(|| {
@@ -98,6 +100,60 @@ pub fn vscode_settings_path(project_dir: impl AsRef<Path>) -> PathBuf {
     project_vscode_dir(project_dir).join(VSCODE_SETTINGS_FILENAME)
 }
 
+/// Creates a temporary directory inside `parent` (or the system temporary
+/// directory). An explicitly chosen `parent` must also have some free space
+/// left. When `keep` is set the directory is not removed once dropped,
+/// otherwise it is also removed if the command is interrupted.
+pub fn create_tempdir(parent: Option<impl AsRef<Path>>, keep: bool) -> Result<CleanupTempDir> {
+    let explicit = parent.is_some();
+    let parent = parent
+        .map(|parent| parent.as_ref().to_path_buf())
+        .unwrap_or_else(std::env::temp_dir);
+    std::fs::create_dir_all(&parent).map_err(|err| {
+        error::user(
+            &format!(
+                "Could not create temporary directory {}: {err}",
+                parent.display()
+            ),
+            "Please make sure you have permission to create directories there or use --tmp-dir",
+        )
+    })?;
+    if explicit {
+        let available = fs4::available_space(&parent).map_err(|err| {
+            error::user(
+                &format!(
+                    "Could not read free space of temporary directory {}: {err}",
+                    parent.display()
+                ),
+                "Please choose another temporary directory with --tmp-dir",
+            )
+        })?;
+        if available < MIN_TMP_DIR_FREE_SPACE {
+            return Err(error::user(
+                &format!(
+                    "Not enough free space in temporary directory {}: {} MiB available",
+                    parent.display(),
+                    available / (1024 * 1024)
+                ),
+                "Please choose another temporary directory with --tmp-dir or AQORA_TMPDIR",
+            ));
+        }
+    }
+    tempfile::Builder::new()
+        .keep(keep)
+        .tempdir_in(&parent)
+        .and_then(|dir| CleanupTempDir::new(dir, keep))
+        .map_err(|err| {
+            error::user(
+                &format!(
+                    "Could not create temporary directory in {}: {err}",
+                    parent.display()
+                ),
+                "Please make sure you have permission to create directories there or use --tmp-dir",
+            )
+        })
+}
+
 pub async fn read_pyproject(project_dir: impl AsRef<Path>) -> Result<PyProject> {
     let path = pyproject_path(&project_dir);
     if !path.exists() {
@@ -120,6 +176,39 @@ pub async fn read_pyproject(project_dir: impl AsRef<Path>) -> Result<PyProject>
     })
 }
 
+/// Merges the use case `include`s and expands the environment variables in
+/// the use case paths of a project read with [`read_pyproject`]. The result
+/// is only meant to run the project locally and must never be written back
+/// or uploaded: it contains values from the local environment, and uploads
+/// keep `include` as written.
+pub fn resolve_pyproject(project_dir: impl AsRef<Path>, project: &mut PyProject) -> Result<()> {
+    let path = pyproject_path(&project_dir);
+    if let Some(AqoraConfig::UseCase(use_case)) = project.aqora_mut() {
+        use_case
+            .resolve_includes(project_dir.as_ref())
+            .map_err(|e| {
+                error::user(
+                    &format!("Failed to resolve includes in {}: {}", path.display(), e),
+                    "Check the `include` paths in [tool.aqora]",
+                )
+            })?;
+        use_case.expand_env().map_err(|e| {
+            error::user(
+                &format!("Failed to expand paths in {}: {}", path.display(), e),
+                "Set the environment variable or escape `$` as `$$`",
+            )
+        })?;
+    }
+    Ok(())
+}
+
+/// Reads the pyproject.toml ready to run locally, see [`resolve_pyproject`]
+pub async fn read_resolved_pyproject(project_dir: impl AsRef<Path>) -> Result<PyProject> {
+    let mut project = read_pyproject(&project_dir).await?;
+    resolve_pyproject(&project_dir, &mut project)?;
+    Ok(project)
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct PipxAppPath {
     #[serde(rename = "__Path__")]
@@ -1,15 +1,51 @@
 use crate::{
     compress::decompress,
+    dirs::create_tempdir,
     error::{self, Result},
     progress_bar::{self, TempProgressStyle},
 };
 use futures::prelude::*;
 use indicatif::ProgressBar;
-use reqwest::header::CONTENT_DISPOSITION;
+use reqwest::{
+    header::{CONTENT_DISPOSITION, RANGE},
+    StatusCode,
+};
 use std::path::Path;
 use url::Url;
 
-pub async fn download_archive(url: Url, dir: impl AsRef<Path>, pb: &ProgressBar) -> Result<()> {
+/// Number of times a dropped download is resumed before giving up
+const MAX_RESUMES: usize = 3;
+
+/// Requests `url` starting at byte `offset`. The server may ignore the range
+/// and answer with the whole file, which callers detect with the status code.
+async fn get(client: &reqwest::Client, url: Url, offset: u64) -> Result<reqwest::Response> {
+    let mut request = client.get(url);
+    if offset > 0 {
+        request = request.header(RANGE, format!("bytes={offset}-"));
+    }
+    request
+        .send()
+        .await
+        .map_err(|e| {
+            error::user(
+                &format!("Failed to download data: {e}"),
+                "Check your internet connection and try again",
+            )
+        })?
+        .error_for_status()
+        .map_err(|e| error::system(&format!("Failed to download data: {e}"), ""))
+}
+
+/// Downloads the archive at `url` and unpacks it into `dir`, resuming the
+/// download if the connection drops. The server does not publish a checksum
+/// for project version files, so archives are neither verified nor cached and
+/// are downloaded again on every install.
+pub async fn download_archive(
+    url: Url,
+    dir: impl AsRef<Path>,
+    tmp_dir: Option<&Path>,
+    pb: &ProgressBar,
+) -> Result<()> {
     let _guard = TempProgressStyle::new(pb);
 
     tokio::fs::create_dir_all(&dir).await.map_err(|e| {
@@ -23,18 +59,7 @@ pub async fn download_archive(url: Url, dir: impl AsRef<Path>, pb: &ProgressBar)
         )
     })?;
     let client = reqwest::Client::new();
-    let response = client
-        .get(url)
-        .send()
-        .await
-        .map_err(|e| {
-            error::user(
-                &format!("Failed to download data: {e}"),
-                "Check your internet connection and try again",
-            )
-        })?
-        .error_for_status()
-        .map_err(|e| error::system(&format!("Failed to download data: {e}"), ""))?;
+    let response = get(&client, url.clone(), 0).await?;
     let attachment = response
         .headers()
         .get(CONTENT_DISPOSITION)
@@ -53,17 +78,32 @@ pub async fn download_archive(url: Url, dir: impl AsRef<Path>, pb: &ProgressBar)
     };
     let mut byte_stream = response.bytes_stream();
 
-    let tar_dir = tempfile::TempDir::new().map_err(|e| {
-        error::user(
-            &format!("Failed to create temporary file: {e}"),
-            "Please make sure you have permission to create files in this directory",
-        )
-    })?;
+    let tar_dir = create_tempdir(tmp_dir, false)?;
     let tar_path = tar_dir.path().join(attachment);
     let mut tar_file = tokio::fs::File::create(&tar_path).await?;
+    let mut written = 0;
+    let mut resumes = 0;
     while let Some(item) = byte_stream.next().await {
-        let item = item?;
+        let item = match item {
+            Ok(item) => item,
+            Err(err) if resumes < MAX_RESUMES => {
+                resumes += 1;
+                tracing::warn!("Download interrupted after {written} bytes, resuming: {err}");
+                let response = get(&client, url.clone(), written).await?;
+                if response.status() != StatusCode::PARTIAL_CONTENT {
+                    tar_file = tokio::fs::File::create(&tar_path).await?;
+                    written = 0;
+                    if show_progress {
+                        pb.set_position(0);
+                    }
+                }
+                byte_stream = response.bytes_stream();
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
         tokio::io::copy(&mut item.as_ref(), &mut tar_file).await?;
+        written += item.len() as u64;
         if show_progress {
             pb.inc(item.len() as u64);
         }
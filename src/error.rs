@@ -9,6 +9,68 @@ human_errors::error_shim!(Error);
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// Failure classes reported as the process exit code so that scripts can tell
+/// them apart, e.g. to retry on [`ErrorClass::Network`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    User = 1,
+    System = 2,
+    Network = 3,
+    Auth = 4,
+}
+
+impl ErrorClass {
+    pub fn of(error: &Error) -> Self {
+        let mut source = std::error::Error::source(error);
+        while let Some(err) = source {
+            if let Some(classified) = err.downcast_ref::<Classified>() {
+                return classified.class;
+            }
+            source = err.source();
+        }
+        if error.is_user() {
+            ErrorClass::User
+        } else {
+            ErrorClass::System
+        }
+    }
+
+    pub fn exit_code(self) -> u8 {
+        self as u8
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("{message}")]
+struct Classified {
+    class: ErrorClass,
+    message: String,
+}
+
+/// A system error caused by a network or otherwise transient failure
+pub fn network(description: &str, advice: &str, cause: impl ToString) -> Error {
+    system_with_internal(
+        description,
+        advice,
+        Classified {
+            class: ErrorClass::Network,
+            message: cause.to_string(),
+        },
+    )
+}
+
+/// A user error caused by missing, expired or rejected credentials
+pub fn auth(description: &str, advice: &str) -> Error {
+    user_with_internal(
+        description,
+        advice,
+        Classified {
+            class: ErrorClass::Auth,
+            message: description.to_string(),
+        },
+    )
+}
+
 impl From<url::ParseError> for Error {
     fn from(e: url::ParseError) -> Self {
         user(&format!("Invalid URL provided: {e}"), "")
@@ -23,9 +85,10 @@ impl From<std::io::Error> for Error {
 
 impl From<reqwest::Error> for Error {
     fn from(e: reqwest::Error) -> Self {
-        system(
+        network(
             &format!("Error sending request to aqora: {e}"),
             "Check your internet connection",
+            e,
         )
     }
 }
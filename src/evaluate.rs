@@ -2,10 +2,13 @@ use crate::error::Result;
 use aqora_runner::pipeline::{EvaluateInputInfo, EvaluationError, EvaluationResult, Evaluator};
 use clap::Args;
 use futures::prelude::*;
-use indicatif::ProgressBar;
+use indicatif::{HumanDuration, ProgressBar};
 use owo_colors::{OwoColorize, Stream as OwoStream, Style};
 use pyo3::prelude::*;
-use std::{path::PathBuf, sync::Arc};
+use std::{path::PathBuf, pin::Pin, sync::Arc, time::Duration};
+use tokio::time::Instant;
+
+type EvaluateItem = Result<EvaluationResult, (EvaluationResult, EvaluationError)>;
 
 #[derive(Args, Debug, Clone)]
 #[command(author, version, about)]
@@ -14,6 +17,75 @@ pub struct Test {
     pub test: Vec<usize>,
 }
 
+/// Guards a run against hung pipelines: warns when no input completes within
+/// `heartbeat` and ends the results with an error once `max_runtime` is exceeded
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Watchdog {
+    pub heartbeat: Option<Duration>,
+    pub max_runtime: Option<Duration>,
+}
+
+impl Watchdog {
+    /// Wraps the results of [`evaluate`]. `in_flight` is given the number of
+    /// completed inputs and returns how many are still being evaluated
+    pub fn watch(
+        self,
+        results: impl Stream<Item = EvaluateItem> + Send + Sync + 'static,
+        in_flight: impl Fn(usize) -> usize + Send + Sync + 'static,
+        pb: ProgressBar,
+    ) -> impl Stream<Item = EvaluateItem> + Send + Sync + 'static {
+        let deadline = self
+            .max_runtime
+            .map(|max_runtime| (Instant::now() + max_runtime, max_runtime));
+        let in_flight = Arc::new(in_flight);
+        let results: Pin<Box<dyn Stream<Item = EvaluateItem> + Send + Sync>> = Box::pin(results);
+        futures::stream::unfold(Some((results, 0)), move |state| {
+            let in_flight = in_flight.clone();
+            let pb = pb.clone();
+            async move {
+                let (mut results, completed) = state?;
+                loop {
+                    let heartbeat = self.heartbeat.map(|heartbeat| Instant::now() + heartbeat);
+                    let wake = match (heartbeat, deadline) {
+                        (Some(heartbeat), Some((deadline, _))) => Some(heartbeat.min(deadline)),
+                        (heartbeat, deadline) => {
+                            heartbeat.or(deadline.map(|(deadline, _)| deadline))
+                        }
+                    };
+                    let Some(wake) = wake else {
+                        let item = results.next().await?;
+                        return Some((item, Some((results, completed + 1))));
+                    };
+                    match tokio::time::timeout_at(wake, results.next()).await {
+                        Ok(item) => return Some((item?, Some((results, completed + 1)))),
+                        Err(_) => {
+                            if let Some((deadline, max_runtime)) = deadline {
+                                if Instant::now() >= deadline {
+                                    let err = EvaluationError::custom(format!(
+                                        "Exceeded the maximum runtime of {} with {} input(s) in flight",
+                                        HumanDuration(max_runtime),
+                                        in_flight(completed)
+                                    ));
+                                    return Some((Err((EvaluationResult::new(), err)), None));
+                                }
+                            }
+                            if let Some(heartbeat) = self.heartbeat {
+                                pb.println(format!(
+                                    "{} No input completed in the last {} ({} input(s) in flight)",
+                                    "[WARN]"
+                                        .if_supports_color(OwoStream::Stdout, |text| text.yellow()),
+                                    HumanDuration(heartbeat),
+                                    in_flight(completed)
+                                ));
+                            }
+                        }
+                    }
+                }
+            }
+        })
+    }
+}
+
 pub fn evaluate(
     evaluator: Evaluator,
     inputs: impl Stream<Item = (usize, PyResult<PyObject>)>,
@@ -119,3 +191,100 @@ pub fn evaluate(
             }
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Runs `results` through `watchdog`, returning the items and how often
+    /// the in flight count was reported, once per warning or error
+    async fn watch(
+        watchdog: Watchdog,
+        results: impl Stream<Item = EvaluateItem> + Send + Sync + 'static,
+    ) -> (Vec<EvaluateItem>, usize) {
+        let reports = Arc::new(AtomicUsize::new(0));
+        let items = watchdog
+            .watch(
+                results,
+                {
+                    let reports = reports.clone();
+                    move |_| {
+                        reports.fetch_add(1, Ordering::SeqCst);
+                        1
+                    }
+                },
+                ProgressBar::hidden(),
+            )
+            .collect::<Vec<_>>()
+            .await;
+        (items, reports.load(Ordering::SeqCst))
+    }
+
+    fn completes_after(delay: Duration) -> impl Stream<Item = EvaluateItem> + Send + Sync {
+        futures::stream::once(async move {
+            tokio::time::sleep(delay).await;
+            Ok(EvaluationResult::new())
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watchdog_heartbeat_warns_without_ending() {
+        let watchdog = Watchdog {
+            heartbeat: Some(Duration::from_secs(60)),
+            max_runtime: None,
+        };
+        let (items, warnings) = watch(watchdog, completes_after(Duration::from_secs(150))).await;
+        assert_eq!(items.len(), 1);
+        assert!(items[0].is_ok());
+        assert_eq!(warnings, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watchdog_heartbeat_resets_on_completion() {
+        let watchdog = Watchdog {
+            heartbeat: Some(Duration::from_secs(60)),
+            max_runtime: None,
+        };
+        let results = completes_after(Duration::from_secs(50))
+            .chain(completes_after(Duration::from_secs(50)));
+        let (items, warnings) = watch(watchdog, results).await;
+        assert_eq!(items.len(), 2);
+        assert_eq!(warnings, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watchdog_max_runtime_ends_with_error() {
+        let watchdog = Watchdog {
+            heartbeat: Some(Duration::from_secs(60)),
+            max_runtime: Some(Duration::from_secs(150)),
+        };
+        let started = Instant::now();
+        let results = completes_after(Duration::from_secs(10)).chain(futures::stream::pending());
+        let (items, reports) = watch(watchdog, results).await;
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_secs(150) && elapsed < Duration::from_secs(151));
+        assert_eq!(items.len(), 2);
+        assert!(items[0].is_ok());
+        let Err((_, err)) = &items[1] else {
+            panic!("expected the run to be aborted");
+        };
+        assert!(
+            err.to_string().contains("Exceeded the maximum runtime"),
+            "{err}"
+        );
+        // warnings at 70s and 130s, then the error
+        assert_eq!(reports, 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watchdog_disabled_passes_results_through() {
+        let (items, reports) = watch(
+            Watchdog::default(),
+            completes_after(Duration::from_secs(3600)),
+        )
+        .await;
+        assert_eq!(items.len(), 1);
+        assert_eq!(reports, 0);
+    }
+}
@@ -0,0 +1,53 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+const GITHUB_OUTPUT: &str = "GITHUB_OUTPUT";
+const GITHUB_STEP_SUMMARY: &str = "GITHUB_STEP_SUMMARY";
+
+fn env_file(name: &str) -> Option<PathBuf> {
+    std::env::var_os(name)
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+}
+
+fn append(path: PathBuf, contents: &str) -> std::io::Result<()> {
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(contents.as_bytes())
+}
+
+/// Appends `name=value` pairs to the GitHub Actions step outputs. Does nothing
+/// outside of GitHub Actions.
+pub fn write_outputs<'a>(
+    outputs: impl IntoIterator<Item = (&'a str, String)>,
+) -> std::io::Result<()> {
+    let Some(path) = env_file(GITHUB_OUTPUT) else {
+        return Ok(());
+    };
+    let mut contents = String::new();
+    for (name, value) in outputs {
+        if value.contains('\n') {
+            let delimiter = format!("ghadelimiter_{}", uuid::Uuid::new_v4());
+            contents.push_str(&format!("{name}<<{delimiter}\n{value}\n{delimiter}\n"));
+        } else {
+            contents.push_str(&format!("{name}={value}\n"));
+        }
+    }
+    append(path, &contents)
+}
+
+/// Appends markdown to the GitHub Actions job summary. Does nothing outside of
+/// GitHub Actions.
+pub fn write_step_summary(markdown: &str) -> std::io::Result<()> {
+    let Some(path) = env_file(GITHUB_STEP_SUMMARY) else {
+        return Ok(());
+    };
+    append(path, markdown)
+}
+
+/// Escapes a value for use in a markdown table cell
+pub fn table_cell(value: &str) -> String {
+    value.replace('|', "\\|").replace('\n', " ")
+}
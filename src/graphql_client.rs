@@ -1,9 +1,12 @@
 use crate::{
     credentials::{get_credentials, Credentials},
-    error::{self, Error, Result},
+    error::{self, Error, ErrorClass, Result},
 };
+use clap::ValueEnum;
 use graphql_client::GraphQLQuery;
 use reqwest::header::{HeaderMap, AUTHORIZATION, USER_AGENT};
+use serde::Serialize;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 use url::Url;
 
@@ -21,6 +24,8 @@ pub enum GraphQLError {
     InvalidHeaderValue(#[from] reqwest::header::InvalidHeaderValue),
     #[error("GraphQL response contained no data")]
     NoData,
+    #[error("Request timed out after {0:?}")]
+    Timeout(Duration),
     #[error(transparent)]
     Other(#[from] Error),
 }
@@ -29,7 +34,7 @@ impl From<GraphQLError> for Error {
     fn from(error: GraphQLError) -> Self {
         match error {
             GraphQLError::Request(error) => {
-                error::system(&format!("Request failed: {error:?}"), "")
+                error::network(&format!("Request failed: {error:?}"), "", error)
             }
             GraphQLError::Response(errors) => error::user(
                 &errors
@@ -41,6 +46,11 @@ impl From<GraphQLError> for Error {
                 "Check your arguments and try again",
             ),
             GraphQLError::NoData => error::system("Invalid response received from server", ""),
+            GraphQLError::Timeout(timeout) => error::network(
+                &format!("Request timed out after {}s", timeout.as_secs_f32()),
+                "Check your internet connection and try again",
+                GraphQLError::Timeout(timeout),
+            ),
             GraphQLError::Other(other) => other,
             GraphQLError::InvalidHeaderValue(_) => {
                 error::system("Invalid header value from client", "")
@@ -49,11 +59,120 @@ impl From<GraphQLError> for Error {
     }
 }
 
+#[derive(GraphQLQuery)]
+#[graphql(
+    query_path = "src/graphql/ping.graphql",
+    schema_path = "src/graphql/schema.graphql",
+    response_derives = "Debug"
+)]
+pub struct Ping;
+
+#[derive(Debug, Clone)]
+pub struct PingResponse {
+    pub latency: Duration,
+    pub server_version: String,
+}
+
+/// How a retry delay is randomized so that clients failing together do not
+/// retry in lockstep
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Jitter {
+    /// Wait exactly the computed delay
+    #[default]
+    None,
+    /// Wait anywhere between zero and the computed delay
+    Full,
+    /// Wait at least half of the computed delay
+    Equal,
+}
+
+impl Jitter {
+    fn apply(self, delay: Duration, rng: &mut impl rand::Rng) -> Duration {
+        match self {
+            Jitter::None => delay,
+            Jitter::Full => delay.mul_f64(rng.gen()),
+            Jitter::Equal => delay / 2 + (delay / 2).mul_f64(rng.gen()),
+        }
+    }
+}
+
+/// How often and how long to wait before retrying a file upload request that
+/// failed with a network error. The delay doubles after each attempt up to
+/// `max_delay`, and no retry is started that would end after `max_elapsed`.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub max_elapsed: Option<Duration>,
+    pub jitter: Jitter,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            max_elapsed: None,
+            jitter: Jitter::None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn delay(&self, attempt: u32, rng: &mut impl rand::Rng) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        self.jitter.apply(delay, rng)
+    }
+
+    pub async fn run<T, F, Fut>(&self, mut f: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        let started = Instant::now();
+        let mut attempt = 0;
+        loop {
+            match f().await {
+                Err(err)
+                    if attempt < self.retries && ErrorClass::of(&err) == ErrorClass::Network =>
+                {
+                    let delay = self.delay(attempt, &mut rand::thread_rng());
+                    if self
+                        .max_elapsed
+                        .map_or(false, |max_elapsed| started.elapsed() + delay > max_elapsed)
+                    {
+                        tracing::warn!("Request failed, giving up after {:?}", started.elapsed());
+                        return Err(err);
+                    }
+                    attempt += 1;
+                    tracing::warn!(
+                        "Request failed, retrying in {delay:?} ({attempt}/{}): {err}",
+                        self.retries
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+/// How long to wait for a connection to the server before giving up
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
 #[derive(Clone)]
 pub struct GraphQLClient {
     client: reqwest::Client,
     url: Url,
     credentials: Option<Credentials>,
+    retry_policy: RetryPolicy,
+    timeout: Option<Duration>,
 }
 
 pub fn graphql_url(url: &Url) -> Result<Url> {
@@ -66,18 +185,41 @@ impl GraphQLClient {
         headers.insert(USER_AGENT, "aqora".parse()?);
         let client = reqwest::Client::builder()
             .default_headers(headers)
+            .connect_timeout(CONNECT_TIMEOUT)
             .build()?;
         Ok(Self {
             client,
             url: graphql_url(&url)?,
             credentials: get_credentials(url.clone()).await?,
+            retry_policy: RetryPolicy::default(),
+            timeout: None,
         })
     }
 
+    /// Fails GraphQL requests that take longer than `timeout` to complete
+    /// with [`GraphQLError::Timeout`]. Requests have no timeout by default.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    pub fn with_retry_policy(self, retry_policy: RetryPolicy) -> Self {
+        Self {
+            retry_policy,
+            ..self
+        }
+    }
+
     pub fn inner(&self) -> &reqwest::Client {
         &self.client
     }
 
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
     pub async fn send<Q: GraphQLQuery>(
         &self,
         variables: Q::Variables,
@@ -92,6 +234,17 @@ impl GraphQLClient {
         }
     }
 
+    /// Issues a trivial query to check that the server is reachable and that
+    /// the stored credentials (if any) are accepted
+    pub async fn ping(&self) -> Result<PingResponse, GraphQLError> {
+        let start = Instant::now();
+        let data = self.send::<Ping>(ping::Variables {}).await?;
+        Ok(PingResponse {
+            latency: start.elapsed(),
+            server_version: data.version.current,
+        })
+    }
+
     async fn post_graphql<Q: GraphQLQuery>(
         &self,
         variables: Q::Variables,
@@ -108,14 +261,144 @@ impl GraphQLClient {
         }
 
         let body = Q::build_query(variables);
-        let reqwest_response = self
+        let mut request = self
             .client
             .post(self.url.clone())
             .headers(headers)
-            .json(&body)
+            .json(&body);
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+        let reqwest_response = request
             .send()
-            .await?;
+            .await
+            .map_err(|err| self.request_error(err))?;
+
+        reqwest_response
+            .json()
+            .await
+            .map_err(|err| self.request_error(err))
+    }
+
+    fn request_error(&self, error: reqwest::Error) -> GraphQLError {
+        match self.timeout {
+            Some(timeout) if error.is_timeout() => GraphQLError::Timeout(timeout),
+            _ => GraphQLError::Request(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn policy(jitter: Jitter) -> RetryPolicy {
+        RetryPolicy {
+            retries: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            max_elapsed: None,
+            jitter,
+        }
+    }
+
+    #[test]
+    fn test_retry_delay_doubles_up_to_max_delay() {
+        let policy = policy(Jitter::None);
+        let mut rng = StdRng::seed_from_u64(0);
+        let delays = (0..6)
+            .map(|attempt| policy.delay(attempt, &mut rng).as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(policy.delay(u32::MAX, &mut rng), policy.max_delay);
+    }
+
+    #[test]
+    fn test_retry_delay_jitter_bounds() {
+        let mut rng = StdRng::seed_from_u64(42);
+        for attempt in 0..6 {
+            let max = policy(Jitter::None).delay(attempt, &mut rng);
+            for _ in 0..100 {
+                let full = policy(Jitter::Full).delay(attempt, &mut rng);
+                assert!(full <= max, "{full:?} > {max:?}");
+                let equal = policy(Jitter::Equal).delay(attempt, &mut rng);
+                assert!(equal >= max / 2 && equal <= max, "{equal:?} not in {max:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_retry_delay_jitter_is_seeded() {
+        let delays = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..4)
+                .map(|attempt| policy(Jitter::Full).delay(attempt, &mut rng))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(delays(7), delays(7));
+        assert_ne!(delays(7), delays(8));
+    }
+
+    #[tokio::test]
+    async fn test_retry_run_gives_up_after_retries() {
+        let calls = AtomicU32::new(0);
+        let policy = RetryPolicy {
+            retries: 2,
+            base_delay: Duration::from_millis(1),
+            ..policy(Jitter::None)
+        };
+        let result = policy
+            .run(|| async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err::<(), _>(error::network("failed", "", "connection reset"))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_run_only_retries_network_errors() {
+        let calls = AtomicU32::new(0);
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            ..policy(Jitter::None)
+        };
+        let result = policy
+            .run(|| async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err::<(), _>(error::system("Could not upload data: [403 Forbidden]", ""))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retry_run_stops_at_max_elapsed() {
+        let calls = AtomicU32::new(0);
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(20),
+            max_elapsed: Some(Duration::from_millis(50)),
+            ..policy(Jitter::None)
+        };
+        let result = policy
+            .run(|| async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err::<(), _>(error::network("failed", "", "connection reset"))
+            })
+            .await;
+        assert!(result.is_err());
+        // waits 20ms then 40ms would end after 60ms, so only one retry is made
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
 
-        Ok(reqwest_response.json().await?)
+    #[test]
+    fn test_timeout_is_network_error() {
+        let error = Error::from(GraphQLError::Timeout(Duration::from_secs(5)));
+        assert_eq!(ErrorClass::of(&error), ErrorClass::Network);
+        assert_eq!(ErrorClass::of(&error).exit_code(), 3);
     }
 }
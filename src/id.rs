@@ -1,5 +1,7 @@
+use crate::error;
 use base64::prelude::*;
 use std::{fmt, str::FromStr};
+use thiserror::Error;
 use uuid::Uuid;
 
 const BASE32_ALPHABET: base32::Alphabet = base32::Alphabet::Rfc4648 { padding: false };
@@ -37,6 +39,22 @@ impl fmt::Display for NodeType {
     }
 }
 
+#[derive(Error, Debug)]
+pub enum IdError {
+    #[error(transparent)]
+    Base64(#[from] base64::DecodeError),
+    #[error("Invalid id length")]
+    Length,
+    #[error("Invalid id version")]
+    Version,
+    #[error(transparent)]
+    Utf8(#[from] std::str::Utf8Error),
+    #[error("{0}")]
+    NodeType(String),
+    #[error(transparent)]
+    Uuid(#[from] uuid::Error),
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Id {
     pub id: Uuid,
@@ -44,21 +62,34 @@ pub struct Id {
 }
 
 impl Id {
-    pub fn parse_node_id(id: impl AsRef<str>) -> Result<Id, Box<dyn std::error::Error>> {
+    pub fn from_node_id(id: impl AsRef<str>) -> Result<Id, IdError> {
         let bytes = BASE64_URL_SAFE_NO_PAD.decode(id.as_ref())?;
         let len = bytes.len();
         if bytes.len() < 1 + 16 {
-            return Err("Invalid id length".into());
+            return Err(IdError::Length);
         }
         if bytes[0] != 0 {
-            return Err("Invalid id version".into());
+            return Err(IdError::Version);
         }
 
-        let ty = std::str::from_utf8(&bytes[1..len - 16])?.parse()?;
+        let ty = std::str::from_utf8(&bytes[1..len - 16])?
+            .parse()
+            .map_err(IdError::NodeType)?;
         let id = Uuid::from_slice(&bytes[len - 16..])?;
         Ok(Id { id, ty })
     }
 
+    /// Parses a node id returned by the server, where a malformed id is a bug
+    /// rather than a user error. `what` names the node in the error message.
+    pub fn parse_server_id(id: impl AsRef<str>, what: &str) -> error::Result<Id> {
+        Self::from_node_id(id).map_err(|err| {
+            error::system(
+                &format!("Could not parse {what} ID: {err}"),
+                "This is a bug, please report it",
+            )
+        })
+    }
+
     pub fn to_node_id(self) -> String {
         let mut bytes = vec![0]; // version
         bytes.extend_from_slice(self.ty.to_string().as_bytes());
@@ -70,3 +101,17 @@ impl Id {
         base32::encode(BASE32_ALPHABET, &self.id.into_bytes()).to_lowercase()
     }
 }
+
+impl fmt::Display for Id {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_node_id())
+    }
+}
+
+impl FromStr for Id {
+    type Err = IdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_node_id(s)
+    }
+}
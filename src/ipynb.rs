@@ -6,8 +6,9 @@ use pyo3::{
     types::{PyDict, PyList},
 };
 use serde::{de, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fmt,
     path::{Path, PathBuf},
 };
@@ -15,6 +16,30 @@ use thiserror::Error;
 use tokio::io::AsyncWriteExt;
 
 const PARAMETERS_TAG: &str = "parameters";
+const GENERATED_MANIFEST_FILENAME: &str = "manifest.json";
+
+/// Line magics known not to run shell commands. Any other line magic may:
+/// aliases such as `%ls` or `%rm`, `%alias` definitions and `%pip` all do
+const SAFE_LINE_MAGICS: &[&str] = &[
+    "time",
+    "timeit",
+    "matplotlib",
+    "config",
+    "precision",
+    "pprint",
+    "who",
+    "whos",
+    "who_ls",
+    "cd",
+    "pwd",
+    "env",
+    "load_ext",
+    "reload_ext",
+    "autoreload",
+    "aimport",
+];
+/// Cell magics known to run their body as Python in this process
+const SAFE_CELL_MAGICS: &[&str] = &["time", "timeit", "capture", "prun"];
 
 const AQORA_PARAMETERS: &str = r#"input = __aqora__args[0]
 context = __aqora__kwargs.get("context")
@@ -166,6 +191,78 @@ fn inject_parameters(cells: &mut Vec<Cell>) {
     }
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    /// Refuse to convert notebooks with `!` commands or magics that are not
+    /// known to be safe, such as `%rm`, `%pip` or `%%bash`
+    pub no_shell_magics: bool,
+    /// Write generated code under this directory, which is added to
+    /// `sys.path`, instead of into an `__aqora__` package next to each
+    /// notebook. Only usable when running in this process: built packages need
+    /// the generated code next to the notebooks.
+    pub generated_dir: Option<PathBuf>,
+}
+
+/// Strips a leading `name = ` so that `files = !ls` is checked like `!ls`
+fn strip_assignment(line: &str) -> &str {
+    let Some((target, value)) = line.split_once('=') else {
+        return line;
+    };
+    if !value.starts_with('=')
+        && !target.is_empty()
+        && target
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | ',' | '.' | ' ' | '\t'))
+    {
+        value.trim_start()
+    } else {
+        line
+    }
+}
+
+fn magic_name(command: &str) -> &str {
+    command
+        .split(|c: char| c.is_whitespace())
+        .next()
+        .unwrap_or_default()
+}
+
+/// Returns the first line of a code cell that may run a shell command
+fn find_shell_magic(cells: &[Cell]) -> Option<String> {
+    cells.iter().find_map(|cell| {
+        let Cell::Code { source, .. } = cell else {
+            return None;
+        };
+        let source = source.0.concat();
+        source.lines().enumerate().find_map(|(index, line)| {
+            let command = strip_assignment(line.trim_start());
+            let is_shell = if let Some(magic) = command.strip_prefix("%%") {
+                index == 0 && !SAFE_CELL_MAGICS.contains(&magic_name(magic))
+            } else if let Some(magic) = command.strip_prefix('%') {
+                !SAFE_LINE_MAGICS.contains(&magic_name(magic))
+            } else {
+                command.starts_with('!')
+            };
+            is_shell.then(|| line.trim().to_string())
+        })
+    })
+}
+
+fn check_shell_magics(path: &Path) -> Result<(), NotebookToPythonFunctionError> {
+    let ipynb: Ipynb = serde_json::from_reader(
+        std::fs::File::open(path)
+            .map_err(|e| NotebookToPythonFunctionError::Read(path.to_path_buf(), e))?,
+    )
+    .map_err(|e| NotebookToPythonFunctionError::Json(path.to_path_buf(), e))?;
+    match find_shell_magic(&ipynb.cells) {
+        Some(line) => Err(NotebookToPythonFunctionError::ShellMagic(
+            path.to_path_buf(),
+            line,
+        )),
+        None => Ok(()),
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum NotebookToPythonFunctionError {
     #[error("Invalid notebook {0}: {1}")]
@@ -176,6 +273,8 @@ pub enum NotebookToPythonFunctionError {
     Write(PathBuf, #[source] std::io::Error),
     #[error("Could not find notebook {0}")]
     CouldNotFindNotebook(PathStr<'static>),
+    #[error("Shell commands are not allowed in notebook {0}: {1}")]
+    ShellMagic(PathBuf, String),
     #[error("nbconvert failed for {0}: {1}")]
     NbconvertFailed(PathBuf, #[source] PyErr),
     #[error(transparent)]
@@ -333,6 +432,7 @@ struct NotebookMeta {
     path: PathStr<'static>,
     notebook_path: PathBuf,
     generated_name: String,
+    generated_root: Option<PathBuf>,
 }
 
 impl NotebookMeta {
@@ -348,24 +448,55 @@ impl NotebookMeta {
         })
     }
 
+    /// The name of the package holding the generated code: `__aqora__` next
+    /// to the notebook, or one package per notebook module under the
+    /// generated root
+    fn package_name(&self) -> String {
+        if self.generated_root.is_some() {
+            format!(
+                "__aqora__{}",
+                base32::encode(
+                    base32::Alphabet::Rfc4648 { padding: false },
+                    self.path.module().to_string().as_bytes(),
+                )
+                .to_lowercase()
+            )
+        } else {
+            "__aqora__".to_string()
+        }
+    }
+
+    fn package_dir(&self) -> Result<PathBuf, NotebookToPythonFunctionError> {
+        Ok(match self.generated_root.as_ref() {
+            Some(root) => root.join(self.package_name()),
+            None => self.notebook_dir()?.join(self.package_name()),
+        })
+    }
+
+    fn generated_dir(&self) -> Result<PathBuf, NotebookToPythonFunctionError> {
+        Ok(self.package_dir()?.join("generated"))
+    }
+
     fn converted_path(&self) -> Result<PathBuf, NotebookToPythonFunctionError> {
         Ok(self
-            .notebook_dir()?
-            .join("__aqora__")
-            .join("generated")
+            .generated_dir()?
             .join(format!("{}.converted.py", self.generated_name)))
     }
 
     fn script_path(&self) -> Result<PathBuf, NotebookToPythonFunctionError> {
         Ok(self
-            .notebook_dir()?
-            .join("__aqora__")
-            .join("generated")
+            .generated_dir()?
             .join(format!("{}.py", self.generated_name)))
     }
 
+    fn is_generated(&self) -> Result<bool, NotebookToPythonFunctionError> {
+        Ok(self.converted_path()?.exists()
+            && self.script_path()?.exists()
+            && self.aqora_module_path()?.exists())
+    }
+
     fn aqora_module_path(&self) -> Result<PathBuf, NotebookToPythonFunctionError> {
-        Ok(self.notebook_dir()?.join("__aqora__").join("__init__.py"))
+        Ok(self.package_dir()?.join("__init__.py"))
     }
 
     fn function_name(&self) -> String {
@@ -373,8 +504,13 @@ impl NotebookMeta {
     }
 
     fn new_path(&self) -> PathStr<'static> {
+        if self.generated_root.is_some() {
+            return format!("{}.{}", self.package_name(), self.function_name())
+                .parse()
+                .unwrap_or_else(|never| match never {});
+        }
         let mut generated_path = self.path.module().into_owned();
-        generated_path.push("__aqora__");
+        generated_path.push(self.package_name());
         generated_path.push(self.function_name());
         generated_path
     }
@@ -422,7 +558,59 @@ async def {func}(*__aqora__args, **__aqora__kwargs):
     }
 }
 
-fn get_meta(env: &PyEnv, path: &PathStr) -> Result<NotebookMeta, NotebookToPythonFunctionError> {
+/// Records the notebooks converted into a `__aqora__/generated` directory so
+/// that conversion can be skipped entirely when nothing changed.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+struct GeneratedManifest {
+    version: String,
+    notebooks: BTreeMap<String, GeneratedManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+struct GeneratedManifestEntry {
+    path: String,
+    notebook: PathBuf,
+    hash: String,
+}
+
+impl GeneratedManifest {
+    fn new() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            notebooks: BTreeMap::new(),
+        }
+    }
+
+    async fn is_up_to_date(&self, path: &Path) -> bool {
+        match tokio::fs::read(path).await {
+            Ok(contents) => serde_json::from_slice::<GeneratedManifest>(&contents)
+                .map(|existing| &existing == self)
+                .unwrap_or(false),
+            Err(_) => false,
+        }
+    }
+
+    async fn write(&self, path: &Path) -> Result<(), NotebookToPythonFunctionError> {
+        let contents = serde_json::to_vec_pretty(self)
+            .map_err(|e| NotebookToPythonFunctionError::Json(path.to_path_buf(), e))?;
+        tokio::fs::write(path, contents)
+            .await
+            .map_err(|e| NotebookToPythonFunctionError::Write(path.to_path_buf(), e))
+    }
+}
+
+async fn hash_notebook(path: &Path) -> Result<String, NotebookToPythonFunctionError> {
+    let contents = tokio::fs::read(path)
+        .await
+        .map_err(|e| NotebookToPythonFunctionError::Read(path.to_path_buf(), e))?;
+    Ok(format!("{:x}", Sha256::digest(contents)))
+}
+
+fn get_meta(
+    env: &PyEnv,
+    path: &PathStr,
+    generated_root: Option<&Path>,
+) -> Result<NotebookMeta, NotebookToPythonFunctionError> {
     let notebook_path = notebook_path(env, path)?;
     let notebook_path = dunce::canonicalize(&notebook_path).map_err(|e| {
         NotebookToPythonFunctionError::Read(
@@ -442,21 +630,85 @@ fn get_meta(env: &PyEnv, path: &PathStr) -> Result<NotebookMeta, NotebookToPytho
         path: path.clone().into_owned(),
         notebook_path,
         generated_name,
+        generated_root: generated_root.map(Path::to_path_buf),
     })
 }
 
+fn add_to_sys_path(dir: &Path) -> Result<(), NotebookToPythonFunctionError> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| NotebookToPythonFunctionError::Write(dir.to_path_buf(), e))?;
+    let dir = dir.to_string_lossy().into_owned();
+    Python::with_gil(|py| {
+        let sys_path = py
+            .import(pyo3::intern!(py, "sys"))?
+            .getattr(pyo3::intern!(py, "path"))?
+            .downcast::<PyList>()?;
+        if !sys_path.contains(&dir)? {
+            sys_path.insert(0, &dir)?;
+        }
+        PyResult::Ok(())
+    })?;
+    Ok(())
+}
+
 async fn convert_notebooks<'a, 'b: 'a>(
     env: &PyEnv,
     paths: impl IntoIterator<Item = &'a mut PathStr<'b>>,
+    options: ConvertOptions,
 ) -> Result<(), NotebookToPythonFunctionError> {
     let paths = paths
         .into_iter()
-        .map(|path| get_meta(env, path).map(|meta| (path, meta)))
+        .map(|path| get_meta(env, path, options.generated_dir.as_deref()).map(|meta| (path, meta)))
         .collect::<Result<Vec<_>, _>>()?;
 
     let mut to_convert = paths.iter().collect::<Vec<_>>();
     to_convert.dedup_by_key(|(path, _)| path);
 
+    // Checked before the up-to-date check so previously generated code is
+    // never reused for a notebook that is no longer allowed
+    if options.no_shell_magics {
+        for (_, meta) in to_convert.iter() {
+            check_shell_magics(&meta.notebook_path)?;
+        }
+    }
+
+    if let Some(generated_dir) = options.generated_dir.as_ref() {
+        add_to_sys_path(generated_dir)?;
+    }
+
+    let mut manifests = HashMap::<PathBuf, GeneratedManifest>::new();
+    let mut all_generated = true;
+    for (_, meta) in to_convert.iter() {
+        all_generated &= meta.is_generated()?;
+        manifests
+            .entry(meta.generated_dir()?.join(GENERATED_MANIFEST_FILENAME))
+            .or_insert_with(GeneratedManifest::new)
+            .notebooks
+            .insert(
+                meta.generated_name.clone(),
+                GeneratedManifestEntry {
+                    path: meta.path.to_string(),
+                    notebook: meta.notebook_path.clone(),
+                    hash: hash_notebook(&meta.notebook_path).await?,
+                },
+            );
+    }
+    if all_generated
+        && futures::future::join_all(
+            manifests
+                .iter()
+                .map(|(path, manifest)| manifest.is_up_to_date(path)),
+        )
+        .await
+        .into_iter()
+        .all(|up_to_date| up_to_date)
+    {
+        for (path, meta) in paths {
+            *path = meta.new_path();
+        }
+        return Ok(());
+    }
+
     let converted =
         futures::future::try_join_all(to_convert.into_iter().map(|(_, meta)| async move {
             notebook_to_script(env, &meta.notebook_path, meta.converted_path()?).await?;
@@ -517,12 +769,20 @@ async fn convert_notebooks<'a, 'b: 'a>(
     ))
     .await?;
 
+    futures::future::try_join_all(
+        manifests
+            .iter()
+            .map(|(path, manifest)| manifest.write(path)),
+    )
+    .await?;
+
     Ok(())
 }
 
 pub async fn convert_submission_notebooks(
     env: &PyEnv,
     submission: &mut AqoraSubmissionConfig,
+    options: ConvertOptions,
 ) -> Result<(), NotebookToPythonFunctionError> {
     convert_notebooks(
         env,
@@ -531,6 +791,7 @@ pub async fn convert_submission_notebooks(
             .values_mut()
             .filter(|f| f.notebook)
             .map(|f| &mut f.path),
+        options,
     )
     .await
 }
@@ -538,6 +799,7 @@ pub async fn convert_submission_notebooks(
 pub async fn convert_use_case_notebooks(
     env: &PyEnv,
     use_case: &mut AqoraUseCaseConfig,
+    options: ConvertOptions,
 ) -> Result<(), NotebookToPythonFunctionError> {
     let mut paths = Vec::new();
     for layer in &mut use_case.layers {
@@ -576,16 +838,19 @@ pub async fn convert_use_case_notebooks(
                 .map(|f| &mut f.path),
         );
     }
-    convert_notebooks(env, paths).await
+    convert_notebooks(env, paths, options).await
 }
 
 pub async fn convert_project_notebooks(
     env: &PyEnv,
     config: &mut AqoraConfig,
+    options: ConvertOptions,
 ) -> Result<(), NotebookToPythonFunctionError> {
     match config {
-        AqoraConfig::UseCase(use_case) => convert_use_case_notebooks(env, use_case).await,
-        AqoraConfig::Submission(submission) => convert_submission_notebooks(env, submission).await,
+        AqoraConfig::UseCase(use_case) => convert_use_case_notebooks(env, use_case, options).await,
+        AqoraConfig::Submission(submission) => {
+            convert_submission_notebooks(env, submission, options).await
+        }
     }
 }
 
@@ -755,6 +1020,115 @@ get_ipython().system('echo "hello"')
         );
     }
 
+    #[test]
+    fn test_find_shell_magic() {
+        let ipynb: Ipynb = serde_json::from_str(EXAMPLE_IPYNB).unwrap();
+        assert_eq!(
+            find_shell_magic(&ipynb.cells).as_deref(),
+            Some("!echo \"hello\"")
+        );
+
+        let code = |source: &str| Cell::Code {
+            source: CellSource::from(source),
+            execution_count: None,
+            metadata: Default::default(),
+            outputs: Default::default(),
+            rest: None,
+        };
+        assert_eq!(
+            find_shell_magic(&[code("import os\n"), code("!rm -rf data\n")]).as_deref(),
+            Some("!rm -rf data")
+        );
+        assert_eq!(
+            find_shell_magic(&[code("files = !ls")]).as_deref(),
+            Some("files = !ls")
+        );
+        assert_eq!(
+            find_shell_magic(&[code("%%bash\nrm -rf data\n")]).as_deref(),
+            Some("%%bash")
+        );
+        assert_eq!(
+            find_shell_magic(&[code("out = %sx ls")]).as_deref(),
+            Some("out = %sx ls")
+        );
+        for line in [
+            "%rm -rf data",
+            "%cp a b",
+            "%ls",
+            "%alias wipe rm -rf %s",
+            "%pip install requests",
+            "%conda install numpy",
+            "%system ls",
+        ] {
+            assert_eq!(find_shell_magic(&[code(line)]).as_deref(), Some(line));
+        }
+        for cell in [
+            "%%sh\nls\n",
+            "%%script bash\nls\n",
+            "%%writefile x.sh\nls\n",
+        ] {
+            assert!(find_shell_magic(&[code(cell)]).is_some(), "{cell}");
+        }
+        assert_eq!(
+            find_shell_magic(&[
+                code("%cd data\n%matplotlib inline\nx = a != b\n"),
+                code("%%time\nx = 1\n"),
+            ]),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_convert_rejects_shell_magics() {
+        pyo3::prepare_freethreaded_python();
+        let temp_dir = async_tempfile::TempDir::new().await.unwrap();
+        let env = PyEnv::init(
+            which::which("uv").unwrap(),
+            &temp_dir.dir_path().join(".venv"),
+            PyEnvOptions::default(),
+        )
+        .await
+        .unwrap();
+        let package = temp_dir.dir_path().join("shell_magic_pkg");
+        std::fs::create_dir_all(&package).unwrap();
+        std::fs::write(package.join("__init__.py"), "").unwrap();
+        std::fs::write(
+            package.join("notebook.ipynb"),
+            serde_json::json!({
+                "cells": [{
+                    "cell_type": "code",
+                    "metadata": {},
+                    "source": ["import os\n", "!rm -rf x\n"],
+                    "outputs": [],
+                    "execution_count": null,
+                }],
+                "metadata": {},
+                "nbformat": 4,
+                "nbformat_minor": 5,
+            })
+            .to_string(),
+        )
+        .unwrap();
+        add_to_sys_path(temp_dir.dir_path()).unwrap();
+
+        let mut path: PathStr = "shell_magic_pkg.notebook".parse().unwrap();
+        let err = convert_notebooks(
+            &env,
+            [&mut path],
+            ConvertOptions {
+                no_shell_magics: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            matches!(&err, NotebookToPythonFunctionError::ShellMagic(_, line) if line == "!rm -rf x"),
+            "{err}"
+        );
+        assert!(!package.join("__aqora__").exists());
+    }
+
     #[tokio::test]
     async fn test_notebook_to_script() {
         pyo3::prepare_freethreaded_python();
@@ -10,6 +10,7 @@ mod download;
 mod error;
 mod evaluate;
 mod git;
+mod github;
 mod graphql_client;
 mod id;
 mod ipynb;
@@ -17,6 +17,11 @@ pub struct LastRunResult {
     pub info: EvaluateAllInfo,
     pub time: chrono::DateTime<chrono::Utc>,
     pub use_case_version: Option<Version>,
+    /// Set when only part of the pipeline was run, e.g. with `--test` or
+    /// `--skip-layer`, or its score was aggregated with `--aggregate`, so the
+    /// run cannot be uploaded as the evaluation
+    #[serde(default)]
+    pub partial: bool,
 }
 
 #[tracing::instrument(skip(env, pb))]
@@ -24,10 +29,12 @@ pub async fn build_package(
     env: &PyEnv,
     input: impl AsRef<Path> + std::fmt::Debug,
     output: impl AsRef<Path> + std::fmt::Debug,
+    tmp_dir: impl AsRef<Path> + std::fmt::Debug,
     pb: &ProgressBar,
 ) -> Result<()> {
     pb.set_message("Building package");
     let mut cmd = env.build_package(input, output);
+    cmd.env("TMPDIR", tmp_dir.as_ref());
     run_command(&mut cmd, pb, Some("Building package"))
         .await
         .map_err(|e| error::system(&format!("Failed to build package: {e}"), ""))
@@ -1,13 +1,15 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::FileTimes;
 use std::{
     ffi::OsString,
+    ops::Deref,
     path::{Path, PathBuf},
 };
-use tempfile::NamedTempFile;
+use tempfile::{NamedTempFile, TempDir};
 
 lazy_static::lazy_static! {
     pub static ref REVERT_FILES: std::sync::Mutex<HashMap<PathBuf, RevertFile>> = std::sync::Mutex::new(HashMap::new());
+    pub static ref CLEANUP_PATHS: std::sync::Mutex<HashSet<PathBuf>> = std::sync::Mutex::new(HashSet::new());
 }
 
 pub struct RevertFile {
@@ -149,6 +151,76 @@ impl Drop for RevertFileHandle {
     }
 }
 
+/// Registers a temporary file or directory to be removed by [`revert_all`]
+/// while the handle is alive. Dropping the handle only unregisters the path,
+/// removing it is left to its owner.
+pub struct CleanupHandle {
+    path: PathBuf,
+}
+
+impl CleanupHandle {
+    pub fn register(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        CLEANUP_PATHS
+            .lock()
+            .map_err(|_| {
+                std::io::Error::new(std::io::ErrorKind::Other, "Could not lock CLEANUP_PATHS")
+            })?
+            .insert(path.clone());
+        Ok(Self { path })
+    }
+}
+
+impl Drop for CleanupHandle {
+    fn drop(&mut self) {
+        if let Ok(mut paths) = CLEANUP_PATHS.lock() {
+            paths.remove(&self.path);
+        }
+    }
+}
+
+/// A [`TempDir`] that is also removed by [`revert_all`] if the command is
+/// interrupted before it is dropped
+pub struct CleanupTempDir {
+    dir: TempDir,
+    _handle: Option<CleanupHandle>,
+}
+
+impl CleanupTempDir {
+    /// Registers `dir` for cleanup unless it is meant to be kept
+    pub fn new(dir: TempDir, keep: bool) -> std::io::Result<Self> {
+        let handle = if keep {
+            None
+        } else {
+            Some(CleanupHandle::register(dir.path())?)
+        };
+        Ok(Self {
+            dir,
+            _handle: handle,
+        })
+    }
+}
+
+impl Deref for CleanupTempDir {
+    type Target = TempDir;
+
+    fn deref(&self) -> &Self::Target {
+        &self.dir
+    }
+}
+
+fn remove_cleanup_path(path: &Path) -> std::io::Result<()> {
+    let res = if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    };
+    match res {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
 pub fn revert_all() -> std::io::Result<()> {
     let mut files = REVERT_FILES.lock().map_err(|_| {
         std::io::Error::new(std::io::ErrorKind::Other, "Could not lock REVERT_FILES")
@@ -157,5 +229,35 @@ pub fn revert_all() -> std::io::Result<()> {
     for (_, file) in files.drain() {
         file.revert()?;
     }
+    let paths = std::mem::take(&mut *CLEANUP_PATHS.lock().map_err(|_| {
+        std::io::Error::new(std::io::ErrorKind::Other, "Could not lock CLEANUP_PATHS")
+    })?);
+    for path in paths {
+        if let Err(err) = remove_cleanup_path(&path) {
+            tracing::error!("Could not remove {}: {}", path.display(), err);
+        }
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_revert_all_removes_cleanup_paths() {
+        let dir = CleanupTempDir::new(tempfile::tempdir().unwrap(), false).unwrap();
+        let archive = dir.path().join("archive.tar.gz");
+        std::fs::write(&archive, b"data").unwrap();
+        let kept = CleanupTempDir::new(tempfile::tempdir().unwrap(), true).unwrap();
+
+        // simulate an interruption: the guards are still alive when the
+        // shutdown path runs
+        revert_all().unwrap();
+
+        assert!(!archive.exists());
+        assert!(!dir.path().exists());
+        assert!(kept.path().exists());
+        assert!(CLEANUP_PATHS.lock().unwrap().is_empty());
+    }
+}
@@ -3,7 +3,7 @@ use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::sync::OnceLock;
 
-use clap::Parser;
+use clap::{error::ErrorKind, CommandFactory, FromArgMatches};
 
 use crate::commands::Cli;
 use crate::dirs::project_bin_dir;
@@ -47,6 +47,16 @@ fn find_venv_aqora(name: impl AsRef<Path>, cli: &Cli) -> Option<PathBuf> {
     }
 }
 
+fn parse_cli(args: Vec<OsString>) -> Cli {
+    let matches = Cli::command().get_matches_from(args);
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    cli.global.resolve_project();
+    if let Err(err) = cli.global.apply_project_defaults(&matches) {
+        Cli::command().error(ErrorKind::InvalidValue, err).exit();
+    }
+    cli
+}
+
 pub fn run<I, T>(args: I) -> u8
 where
     I: IntoIterator<Item = T>,
@@ -54,10 +64,10 @@ where
 {
     let cli = if cfg!(debug_assertions) {
         let args = args.into_iter().map(|s| s.into()).collect::<Vec<_>>();
-        Cli::parse_from(args.clone())
+        parse_cli(args)
     } else {
         let mut args = args.into_iter().map(|s| s.into()).collect::<Vec<_>>();
-        let cli = Cli::parse_from(args.clone());
+        let cli = parse_cli(args.clone());
         let name = args.remove(0);
         if let Some(venv_aqora) = find_venv_aqora(name, &cli) {
             args.push("--ignore-venv-aqora".into());
@@ -72,10 +82,5 @@ where
 
     let tokio = tokio_runtime();
     pyo3_asyncio::tokio::init_with_runtime(tokio).unwrap();
-    let success = tokio.block_on(async { cli.run().await });
-    if success {
-        0
-    } else {
-        1
-    }
+    tokio.block_on(async { cli.run().await })
 }
@@ -1,8 +1,14 @@
-use std::path::Path;
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
 use futures::prelude::*;
 use graphql_client::GraphQLQuery;
-use indicatif::ProgressBar;
+use indicatif::{HumanBytes, ProgressBar};
 use reqwest::{
     header::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE},
     Body, Response,
@@ -38,13 +44,21 @@ struct CompleteMultipartUpload;
 // const CHUNK_SIZE: u64 = 1024 * 1024 * 100;
 const CHUNK_SIZE: u64 = 1024 * 1024 * 10;
 
+/// The progress bar of the file being uploaded and the shared total progress
+/// bar across all files
+#[derive(Clone, Copy)]
+struct UploadProgress<'a> {
+    pb: &'a ProgressBar,
+    total_pb: &'a ProgressBar,
+}
+
 async fn do_upload(
     client: &reqwest::Client,
     body: impl AsyncRead + Send + 'static,
     upload_url: &Url,
     content_length: u64,
     content_type: Option<&str>,
-    pb: &ProgressBar,
+    progress: UploadProgress<'_>,
 ) -> Result<Response> {
     let mut request = client
         .put(upload_url.to_string())
@@ -53,59 +67,106 @@ async fn do_upload(
     if let Some(content_type) = content_type {
         request = request.header(CONTENT_TYPE, content_type);
     }
-    let pb = pb.clone();
-    let body = Body::wrap_stream(ReaderStream::new(body).inspect(move |chunk| {
-        if let Ok(chunk) = chunk.as_ref() {
-            pb.inc(chunk.len() as u64);
-        }
-    }));
-    let response = request.body(body).send().await?;
-    if !response.status().is_success() {
-        Err(error::system(
-            &format!(
+    let sent = Arc::new(AtomicU64::new(0));
+    let body = {
+        let pb = progress.pb.clone();
+        let total_pb = progress.total_pb.clone();
+        let sent = sent.clone();
+        Body::wrap_stream(ReaderStream::new(body).inspect(move |chunk| {
+            if let Ok(chunk) = chunk.as_ref() {
+                pb.inc(chunk.len() as u64);
+                total_pb.inc(chunk.len() as u64);
+                sent.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            }
+        }))
+    };
+    let result = match request.body(body).send().await {
+        Ok(response) if response.status().is_success() => Ok(response),
+        Ok(response) => {
+            let status = response.status();
+            let message = format!(
                 "Could not upload data: [{}] {}",
-                response.status(),
+                status,
                 response.text().await.unwrap_or("".to_string())
-            ),
-            "",
-        ))
-    } else {
-        Ok(response)
+            );
+            if status.is_server_error() {
+                Err(error::network(&message, "", status))
+            } else {
+                Err(error::system(&message, ""))
+            }
+        }
+        Err(err) => Err(err.into()),
+    };
+    if result.is_err() {
+        // bytes sent by a failed request will be sent again on retry
+        let sent = sent.load(Ordering::Relaxed);
+        progress.pb.inc_length(sent);
+        progress.total_pb.inc_length(sent);
     }
+    result
 }
 
 async fn simple_upload(
-    client: &reqwest::Client,
-    file: File,
+    client: &GraphQLClient,
+    path: &Path,
     upload_url: &Url,
     content_length: u64,
     content_type: Option<&str>,
-    pb: &ProgressBar,
+    progress: UploadProgress<'_>,
 ) -> Result<()> {
+    let pb = progress.pb;
     let _guard = TempProgressStyle::new(pb);
     pb.reset();
     pb.set_style(progress_bar::pretty_bytes());
     pb.disable_steady_tick();
     pb.set_position(0);
     pb.set_length(content_length);
-    let _ = do_upload(client, file, upload_url, content_length, content_type, pb).await?;
+    client
+        .retry_policy()
+        .run(|| async move {
+            let file = File::open(path).await?;
+            do_upload(
+                client.inner(),
+                file,
+                upload_url,
+                content_length,
+                content_type,
+                progress,
+            )
+            .await
+        })
+        .await?;
     Ok(())
 }
 
 async fn upload_part(
-    client: &reqwest::Client,
-    path: impl AsRef<Path>,
+    client: &GraphQLClient,
+    path: &Path,
     chunk_number: u64,
     content_length: u64,
     content_type: Option<&str>,
     upload_url: Url,
-    pb: &ProgressBar,
+    progress: UploadProgress<'_>,
 ) -> Result<String> {
-    let mut file = tokio::fs::File::open(path).await?;
-    file.seek(SeekFrom::Start(chunk_number * CHUNK_SIZE))
+    let upload_url = &upload_url;
+    let response = client
+        .retry_policy()
+        .run(|| async move {
+            let mut file = File::open(path).await?;
+            file.seek(SeekFrom::Start(chunk_number * CHUNK_SIZE))
+                .await?;
+            let chunk = file.take(CHUNK_SIZE);
+            do_upload(
+                client.inner(),
+                chunk,
+                upload_url,
+                content_length,
+                content_type,
+                progress,
+            )
+            .await
+        })
         .await?;
-    let chunk = file.take(CHUNK_SIZE);
-    let response = do_upload(client, chunk, &upload_url, content_length, content_type, pb).await?;
     Ok(response
         .headers()
         .get("ETag")
@@ -121,8 +182,9 @@ async fn multipart_upload(
     id: &Id,
     content_length: u64,
     content_type: Option<&str>,
-    pb: &ProgressBar,
+    progress: UploadProgress<'_>,
 ) -> Result<()> {
+    let pb = progress.pb;
     let mut chunks = [CHUNK_SIZE].repeat((content_length / CHUNK_SIZE) as usize);
     if content_length % CHUNK_SIZE != 0 {
         chunks.push(content_length % CHUNK_SIZE);
@@ -157,13 +219,13 @@ async fn multipart_upload(
             .into_iter()
             .map(|(i, url, content_length)| {
                 upload_part(
-                    client.inner(),
+                    client,
                     path.as_ref(),
                     i as u64,
                     content_length,
                     content_type,
                     url,
-                    pb,
+                    progress,
                 )
             }),
     )
@@ -180,7 +242,11 @@ async fn multipart_upload(
     Ok(())
 }
 
-#[tracing::instrument(ret, err, skip(client, pb))]
+/// Uploads a file, reporting progress on `pb` and adding its progress to the
+/// shared `total_pb`, whose length must already include the file's size. Each
+/// request (the whole file for small files, otherwise each part) is retried on
+/// its own according to the client's retry policy.
+#[tracing::instrument(ret, err, skip(client, pb, total_pb))]
 pub async fn upload_project_version_file(
     client: &GraphQLClient,
     path: impl AsRef<Path> + std::fmt::Debug,
@@ -188,20 +254,22 @@ pub async fn upload_project_version_file(
     content_type: Option<&str>,
     upload_url: Option<&Url>,
     pb: &ProgressBar,
+    total_pb: &ProgressBar,
 ) -> Result<()> {
-    let file = tokio::fs::File::open(path.as_ref()).await?;
-    let content_len = file.metadata().await?.len();
+    let content_len = tokio::fs::metadata(path.as_ref()).await?.len();
+    pb.set_message(format!("{} ({})", pb.message(), HumanBytes(content_len)));
+    let progress = UploadProgress { pb, total_pb };
     if content_len < CHUNK_SIZE && upload_url.is_some() {
         simple_upload(
-            client.inner(),
-            file,
+            client,
+            path.as_ref(),
             upload_url.unwrap(),
             content_len,
             content_type,
-            pb,
+            progress,
         )
         .await
     } else {
-        multipart_upload(client, path, id, content_len, content_type, pb).await
+        multipart_upload(client, path, id, content_len, content_type, progress).await
     }
 }